@@ -1,5 +1,125 @@
-use std::io::Result;
+use prost::Message;
+use prost_types::{DescriptorProto, FileDescriptorSet};
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::io::{Error, Result};
+use std::path::PathBuf;
+use std::process::Command;
+
+const PROTO: &str = "src/onebot_idl/onebot_frame.proto";
+const INCLUDE: &str = "src/onebot_idl";
+
+const SERDE_DERIVE: &str = "#[derive(serde::Serialize, serde::Deserialize)]";
+
 fn main() -> Result<()> {
-    prost_build::compile_protos(&["src/onebot_idl/onebot_frame.proto"], &["src/onebot_idl"])?;
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let descriptor_path = out_dir.join("onebot_descriptor.bin");
+
+    // 先用 protoc 得到描述，生成代码时需要区分 message、enum 和 oneof
+    let status = Command::new(prost_build::protoc())
+        .arg("--include_imports")
+        .arg("-o")
+        .arg(&descriptor_path)
+        .arg("-I")
+        .arg(INCLUDE)
+        .arg("-I")
+        .arg(prost_build::protoc_include())
+        .arg(PROTO)
+        .status()?;
+    if !status.success() {
+        return Err(Error::other(format!("protoc failed: {}", status)));
+    }
+    let descriptor_set = FileDescriptorSet::decode(fs::read(&descriptor_path)?.as_slice())?;
+
+    let mut config = prost_build::Config::new();
+    serde_attributes(&mut config, &descriptor_set);
+    config.compile_protos(&[PROTO], &[INCLUDE])?;
+
+    fs::write(out_dir.join("frame_type.rs"), gen_frame_type(&descriptor_set)?)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+///
+/// 所有生成的类型实现 serde，用于 JSON 编码
+///
+/// message 缺少的字段使用默认值，和 protobuf 一致；oneof 的变体名使用 proto 中的字段名。
+/// prost 只使用匹配最具体的路径的属性，所以每个类型都要单独设置
+///
+fn serde_attributes(config: &mut prost_build::Config, descriptor_set: &FileDescriptorSet) {
+    config.type_attribute(".", SERDE_DERIVE);
+    for file in &descriptor_set.file {
+        let package = format!(".{}", file.package());
+        for message in &file.message_type {
+            message_serde_attributes(config, &package, message);
+        }
+        for enum_type in &file.enum_type {
+            config.type_attribute(format!("{}.{}", package, enum_type.name()), SERDE_DERIVE);
+        }
+    }
+}
+
+fn message_serde_attributes(config: &mut prost_build::Config, parent: &str, message: &DescriptorProto) {
+    let path = format!("{}.{}", parent, message.name());
+    config.type_attribute(&path, format!("{}\n#[serde(default)]", SERDE_DERIVE));
+    for oneof in &message.oneof_decl {
+        config.type_attribute(format!("{}.{}", path, oneof.name()), format!("{}\n#[serde(rename_all = \"snake_case\")]", SERDE_DERIVE));
+    }
+    for enum_type in &message.enum_type {
+        config.type_attribute(format!("{}.{}", path, enum_type.name()), SERDE_DERIVE);
+    }
+    for nested in &message.nested_type {
+        message_serde_attributes(config, &path, nested);
+    }
+}
+
+/// 根据 Frame 的 oneof data 和 FrameType 生成 get_frame_type，每个 Data 都必须有对应的 FrameType
+fn gen_frame_type(descriptor_set: &FileDescriptorSet) -> Result<String> {
+    let frame = descriptor_set.file.iter()
+        .filter(|file| file.package() == "onebot")
+        .flat_map(|file| file.message_type.iter())
+        .find(|message| message.name() == "Frame")
+        .ok_or_else(|| Error::other("message onebot.Frame not found"))?;
+    let data_index = frame.oneof_decl.iter()
+        .position(|oneof| oneof.name() == "data")
+        .ok_or_else(|| Error::other("oneof Frame.data not found"))? as i32;
+    let frame_types: Vec<String> = frame.enum_type.iter()
+        .filter(|e| e.name() == "FrameType")
+        .flat_map(|e| e.value.iter())
+        .map(|value| to_upper_camel(value.name()))
+        .collect();
+
+    let mut arms = String::new();
+    let mut missing = Vec::new();
+    for field in frame.field.iter().filter(|field| field.oneof_index == Some(data_index)) {
+        let variant = to_upper_camel(field.name());
+        let frame_type = format!("T{}", variant);
+        if !frame_types.contains(&frame_type) {
+            missing.push(variant);
+            continue;
+        }
+        writeln!(arms, "        Data::{}(_) => FrameType::{},", variant, frame_type).unwrap();
+    }
+    if !missing.is_empty() {
+        return Err(Error::other(format!("FrameType missing for Frame.data variants: {}", missing.join(", "))));
+    }
+
+    Ok(format!(
+        "/// Data 对应的 FrameType，由 build.rs 根据 onebot_frame.proto 生成\n\
+         pub fn get_frame_type(data: &Data) -> FrameType {{\n    match data {{\n{}    }}\n}}\n",
+        arms
+    ))
+}
+
+/// 和 prost 生成的 oneof/enum 名称保持一致，send_private_msg_req -> SendPrivateMsgReq
+fn to_upper_camel(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
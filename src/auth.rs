@@ -0,0 +1,201 @@
+use axum::http::header::{HeaderMap, AUTHORIZATION};
+use axum::http::StatusCode;
+use std::collections::{HashMap, HashSet};
+
+///
+/// websocket 连接鉴权配置
+///
+/// 客户端通过 Authorization 头（Bearer xxx 或 Token xxx）或 access_token 查询参数提供 token，
+/// 通过 x-self-id 头提供机器人 QQ 号
+///
+#[derive(Clone, Default)]
+pub struct Auth {
+    access_token: Option<String>,
+    bot_tokens: HashMap<i64, String>,
+    allowed_bots: Option<HashSet<i64>>,
+}
+
+/// 鉴权失败原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+    /// 没有 x-self-id 头
+    MissingSelfId,
+    /// x-self-id 不是合法的 QQ 号
+    InvalidSelfId,
+    /// 需要 token 但没有提供
+    MissingToken,
+    /// token 不正确
+    InvalidToken,
+    /// bot_id 不在允许列表中
+    BotNotAllowed,
+}
+
+impl AuthError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::MissingSelfId | AuthError::InvalidSelfId => StatusCode::BAD_REQUEST,
+            AuthError::MissingToken | AuthError::InvalidToken => StatusCode::UNAUTHORIZED,
+            AuthError::BotNotAllowed => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+impl Auth {
+    /// 不做任何限制，只要求 x-self-id 合法
+    pub fn new() -> Auth {
+        Default::default()
+    }
+
+    /// 所有 bot 共用的 token
+    pub fn access_token(mut self, token: &str) -> Auth {
+        self.access_token = Some(token.to_string());
+        self
+    }
+
+    /// 指定 bot 使用的 token，优先于 access_token
+    pub fn bot_token(mut self, bot_id: i64, token: &str) -> Auth {
+        self.bot_tokens.insert(bot_id, token.to_string());
+        self
+    }
+
+    /// 允许连接的 bot，调用后只有列表中的 bot 可以连接
+    pub fn allow_bot(mut self, bot_id: i64) -> Auth {
+        self.allowed_bots.get_or_insert_with(HashSet::new).insert(bot_id);
+        self
+    }
+
+    ///
+    /// 检查 websocket 升级请求
+    ///
+    /// @param headers 请求头
+    /// @param query   查询参数
+    /// @return 通过时返回 bot_id
+    ///
+    pub fn authorize(&self, headers: &HeaderMap, query: &HashMap<String, String>) -> Result<i64, AuthError> {
+        let bot_id = headers.get("x-self-id")
+            .ok_or(AuthError::MissingSelfId)?
+            .to_str().ok()
+            .and_then(|id| id.trim().parse::<i64>().ok())
+            .filter(|id| *id > 0)
+            .ok_or(AuthError::InvalidSelfId)?;
+
+        let expected = self.bot_tokens.get(&bot_id).or(self.access_token.as_ref());
+        if let Some(expected) = expected {
+            let token = header_token(headers)
+                .or_else(|| query.get("access_token").map(String::as_str))
+                .ok_or(AuthError::MissingToken)?;
+            if !constant_time_eq(token.as_bytes(), expected.as_bytes()) {
+                return Err(AuthError::InvalidToken);
+            }
+        }
+
+        if let Some(allowed_bots) = &self.allowed_bots {
+            if !allowed_bots.contains(&bot_id) {
+                return Err(AuthError::BotNotAllowed);
+            }
+        }
+        Ok(bot_id)
+    }
+}
+
+/// 从 Authorization 头中取出 token，支持 Bearer 和 Token 两种写法
+fn header_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?.trim();
+    let (scheme, token) = value.split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") || scheme.eq_ignore_ascii_case("token") {
+        Some(token.trim())
+    } else {
+        None
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn query(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn missing_self_id() {
+        let err = Auth::new().authorize(&headers(&[]), &query(&[])).unwrap_err();
+        assert_eq!(err, AuthError::MissingSelfId);
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn invalid_self_id() {
+        for id in &["abc", "0", "-1", ""] {
+            let err = Auth::new().authorize(&headers(&[("x-self-id", id)]), &query(&[])).unwrap_err();
+            assert_eq!(err, AuthError::InvalidSelfId);
+        }
+        let mut non_utf8 = HeaderMap::new();
+        non_utf8.insert("x-self-id", axum::http::HeaderValue::from_bytes(&[0xff, 0xfe]).unwrap());
+        assert_eq!(Auth::new().authorize(&non_utf8, &query(&[])), Err(AuthError::InvalidSelfId));
+    }
+
+    #[test]
+    fn missing_token() {
+        let auth = Auth::new().access_token("secret");
+        let err = auth.authorize(&headers(&[("x-self-id", "123")]), &query(&[])).unwrap_err();
+        assert_eq!(err, AuthError::MissingToken);
+        assert_eq!(err.status_code(), StatusCode::UNAUTHORIZED);
+
+        let basic = headers(&[("x-self-id", "123"), ("authorization", "Basic c2VjcmV0")]);
+        assert_eq!(auth.authorize(&basic, &query(&[])), Err(AuthError::MissingToken));
+    }
+
+    #[test]
+    fn invalid_token() {
+        let auth = Auth::new().access_token("secret");
+        let bearer = headers(&[("x-self-id", "123"), ("authorization", "Bearer wrong")]);
+        assert_eq!(auth.authorize(&bearer, &query(&[])), Err(AuthError::InvalidToken));
+        let by_query = headers(&[("x-self-id", "123")]);
+        assert_eq!(auth.authorize(&by_query, &query(&[("access_token", "wrong")])), Err(AuthError::InvalidToken));
+    }
+
+    #[test]
+    fn bot_not_allowed() {
+        let auth = Auth::new().allow_bot(123);
+        let err = auth.authorize(&headers(&[("x-self-id", "456")]), &query(&[])).unwrap_err();
+        assert_eq!(err, AuthError::BotNotAllowed);
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn bot_token_overrides_access_token() {
+        let auth = Auth::new().access_token("secret").bot_token(456, "other");
+        let global = headers(&[("x-self-id", "456"), ("authorization", "Bearer secret")]);
+        assert_eq!(auth.authorize(&global, &query(&[])), Err(AuthError::InvalidToken));
+        let own = headers(&[("x-self-id", "456"), ("authorization", "Bearer other")]);
+        assert_eq!(auth.authorize(&own, &query(&[])), Ok(456));
+    }
+
+    #[test]
+    fn accepted() {
+        let auth = Auth::new().access_token("secret").allow_bot(123);
+        let bearer = headers(&[("x-self-id", "123"), ("authorization", "Bearer secret")]);
+        assert_eq!(auth.authorize(&bearer, &query(&[])), Ok(123));
+        let token = headers(&[("x-self-id", "123"), ("authorization", "Token secret")]);
+        assert_eq!(auth.authorize(&token, &query(&[])), Ok(123));
+        let by_query = headers(&[("x-self-id", "123")]);
+        assert_eq!(auth.authorize(&by_query, &query(&[("access_token", "secret")])), Ok(123));
+        assert_eq!(Auth::new().authorize(&by_query, &query(&[])), Ok(123));
+    }
+}
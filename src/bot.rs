@@ -1,42 +1,189 @@
 use crate::onebot;
-use std::sync::Arc;
-use tokio::sync::{Mutex, oneshot, mpsc};
+use crate::error::BotError;
+use crate::middleware::{CallNext, Middlewares};
+use crate::segment;
+use crate::session::{MessageEvent, Sessions};
+use crate::shutdown::InFlight;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::Instrument;
+use tokio::sync::{oneshot, mpsc};
 use std::collections::HashMap;
 use crate::onebot::frame::{Data, FrameType};
 use crate::onebot::*;
 
+/// API 调用默认超时时间
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[derive(Clone)]
 pub struct Bot {
     pub bot_id: i64,
     pub api_sender: mpsc::Sender<onebot::Frame>,
     pub resp_promises: Arc<Mutex<HashMap<String, oneshot::Sender<onebot::Frame>>>>,
+    pub timeout: Duration,
+    /// 严格模式，发送前检查消息段是否符合规范
+    pub strict: bool,
+    /// 等待用户下一条消息的会话
+    pub sessions: Sessions,
+    /// 事件和 API 调用经过的中间件
+    pub middlewares: Middlewares,
+    /// 正在等待响应的 API 调用，退出时等待它们完成
+    pub(crate) calls: InFlight,
+}
+
+/// 等待响应期间持有，结束（收到响应、超时、future 被 drop）时移除 echo
+struct PendingGuard<'a> {
+    resp_promises: &'a Mutex<HashMap<String, oneshot::Sender<onebot::Frame>>>,
+    echo: &'a str,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut resp_promises) = self.resp_promises.lock() {
+            resp_promises.remove(self.echo);
+        }
+    }
 }
 
 impl Bot {
-    pub async fn send_and_wait(&mut self, data: Data) -> Option<Data> {
+    pub fn new(bot_id: i64, api_sender: mpsc::Sender<onebot::Frame>) -> Bot {
+        Bot {
+            bot_id,
+            api_sender,
+            resp_promises: Default::default(),
+            timeout: DEFAULT_TIMEOUT,
+            strict: false,
+            sessions: Default::default(),
+            middlewares: Default::default(),
+            calls: InFlight::new(),
+        }
+    }
+
+    ///
+    /// 返回使用指定超时时间的 Bot，用于单次调用
+    ///
+    /// bot.with_timeout(Duration::from_secs(5)).get_group_list().await
+    ///
+    pub fn with_timeout(&self, timeout: Duration) -> Bot {
+        Bot { timeout, ..self.clone() }
+    }
+
+    /// 返回使用指定中间件的 Bot，会替换原有的中间件
+    pub fn with_middlewares(&self, middlewares: Middlewares) -> Bot {
+        Bot { middlewares, ..self.clone() }
+    }
+
+    ///
+    /// 把 API 响应交给对应 echo 的调用者
+    ///
+    /// @param frame 收到的响应
+    /// @return 是否有调用者在等待该响应
+    ///
+    pub fn handle_response(&self, frame: onebot::Frame) -> bool {
+        let resp_sender = match self.resp_promises.lock() {
+            Ok(mut resp_promises) => resp_promises.remove(frame.echo.as_str()),
+            Err(_) => None,
+        };
+        match resp_sender {
+            Some(resp_sender) => resp_sender.send(frame).is_ok(),
+            None => false,
+        }
+    }
+
+    ///
+    /// 返回开启或关闭严格模式的 Bot，严格模式下消息段不符合规范的请求不会发送，返回 BotError::InvalidSegment
+    ///
+    pub fn with_strict(&self, strict: bool) -> Bot {
+        Bot { strict, ..self.clone() }
+    }
+
+    ///
+    /// 等待用户的下一条消息，用于多步对话
+    ///
+    /// @param user_id  QQ 号
+    /// @param group_id 群号，None 表示等待私聊消息
+    /// @param timeout  超时时间
+    /// @return 下一条消息，超时返回 BotError::Timeout，被取消返回 BotError::Cancelled
+    ///
+    pub async fn wait_next(&self, user_id: i64, group_id: Option<i64>, timeout: Duration) -> Result<MessageEvent, BotError> {
+        self.sessions.wait_next(user_id, group_id, timeout, |_| true).await
+    }
+
+    ///
+    /// 等待用户下一条满足 filter 的消息，不满足的消息照常分发给 EventHandler
+    ///
+    pub async fn wait_next_filtered<F>(&self, user_id: i64, group_id: Option<i64>, timeout: Duration, filter: F) -> Result<MessageEvent, BotError>
+    where
+        F: Fn(&MessageEvent) -> bool + Send + Sync + 'static,
+    {
+        self.sessions.wait_next(user_id, group_id, timeout, filter).await
+    }
+
+    pub async fn send_and_wait(&mut self, data: Data) -> Result<Data, BotError> {
+        let timeout = self.timeout;
+        self.send_and_wait_with_timeout(data, timeout).await
+    }
+
+    pub async fn send_and_wait_with_timeout(&mut self, data: Data, timeout: Duration) -> Result<Data, BotError> {
+        if self.strict {
+            let errors: Vec<_> = request_messages(&data).iter()
+                .filter_map(|message| segment::validate(message).err())
+                .flatten()
+                .collect();
+            if !errors.is_empty() {
+                return Err(BotError::InvalidSegment(errors));
+            }
+        }
+
         // 构造API请求
         let echo: String = uuid::Uuid::new_v4().to_simple().to_string();
-        let req_frame_type = get_frame_type(&data).into();
+        let req_frame_type = get_frame_type(&data);
+        let span = tracing::info_span!("api_call", bot_id = self.bot_id, echo = %echo, frame_type = ?req_frame_type);
         let api_req_frame = Frame {
             bot_id: self.bot_id,
-            frame_type: req_frame_type,
-            echo: echo.clone(),
+            frame_type: req_frame_type.into(),
+            echo,
             ok: true,
             extra: Default::default(),
             data: Some(data),
         };
 
-        // 发送API请求
-        let mut buf = Vec::new();
-        prost::Message::encode(&api_req_frame, &mut buf);
-        let api_sender = mpsc::Sender::clone(&self.api_sender);
-        api_sender.send(api_req_frame).await;
+        // 经过中间件后发送
+        let start = std::time::Instant::now();
+        let next = CallNext { bot: self, middlewares: &self.middlewares, timeout };
+        let result = next.run(api_req_frame).instrument(span.clone()).await
+            .and_then(|api_resp_frame| {
+                if !api_resp_frame.ok {
+                    return Err(BotError::Remote(api_resp_frame.extra));
+                }
+                api_resp_frame.data.ok_or(BotError::UnexpectedData(None))
+            });
+        let latency_ms = start.elapsed().as_millis() as u64;
+        span.in_scope(|| match &result {
+            Ok(_) => tracing::debug!(latency_ms, "api call finished"),
+            Err(err) => tracing::warn!(latency_ms, error = %err, "api call failed"),
+        });
+        result
+    }
 
-        // 等待API响应
-        let (mut resp_sender, mut resp_receiver) = oneshot::channel();
-        self.resp_promises.lock().await.insert(echo.clone(), resp_sender);
-        let api_resp_frame = resp_receiver.await.unwrap();
-        return api_resp_frame.data;
+    ///
+    /// 发送请求 Frame 并等待对应 echo 的响应 Frame，不经过中间件
+    ///
+    pub(crate) async fn send_frame(&self, frame: Frame, timeout: Duration) -> Result<Frame, BotError> {
+        // 先注册响应，再发送API请求，避免响应先于注册到达而被丢弃
+        let echo = frame.echo.clone();
+        let _call = self.calls.enter();
+        let (resp_sender, resp_receiver) = oneshot::channel();
+        self.resp_promises.lock().map_err(|_| BotError::ChannelClosed)?.insert(echo.clone(), resp_sender);
+        let _guard = PendingGuard { resp_promises: &self.resp_promises, echo: &echo };
+
+        // 发送队列已满时同样计入超时
+        let call = async {
+            self.api_sender.send(frame).await.map_err(|_| BotError::ChannelClosed)?;
+            // 等待API响应
+            resp_receiver.await.map_err(|_| BotError::ChannelClosed)
+        };
+        tokio::time::timeout(timeout, call).await.unwrap_or(Err(BotError::Timeout))
     }
 
     ///
@@ -46,16 +193,16 @@ impl Bot {
     /// @param content          消息内容
     /// @return 结果
     ///
-    pub async fn send_private_message<T: Into<Vec<Message>>>(&mut self, user_id: i64, message: T) -> Option<SendPrivateMsgResp> {
+    pub async fn send_private_message<T: Into<Vec<Message>>>(&mut self, user_id: i64, message: T) -> Result<SendPrivateMsgResp, BotError> {
         let resp = self.send_and_wait(Data::SendPrivateMsgReq(SendPrivateMsgReq {
             user_id,
             message: message.into(),
             auto_escape: false,
-        })).await;
-        if let Some(Data::SendPrivateMsgResp(resp)) = resp {
-            Some(resp)
+        })).await?;
+        if let Data::SendPrivateMsgResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -66,16 +213,40 @@ impl Bot {
     /// @param content          消息
     /// @return 结果
     ///
-    pub async fn send_group_message<T: Into<Vec<Message>>>(&mut self, group_id: i64, message: T) -> Option<SendGroupMsgResp> {
+    pub async fn send_group_message<T: Into<Vec<Message>>>(&mut self, group_id: i64, message: T) -> Result<SendGroupMsgResp, BotError> {
         let resp = self.send_and_wait(Data::SendGroupMsgReq(SendGroupMsgReq {
             group_id,
             message: message.into(),
             auto_escape: false,
-        })).await;
-        if let Some(Data::SendGroupMsgResp(resp)) = resp {
-            Some(resp)
+        })).await?;
+        if let Data::SendGroupMsgResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 发送消息
+    ///
+    /// @param message_type 消息类型，支持 private、group，分别对应私聊、群组，如为空，则根据传入的 *_id 参数判断
+    /// @param user_id      对方 QQ 号（消息类型为 private 时需要）
+    /// @param group_id     群号（消息类型为 group 时需要）
+    /// @param message      消息内容
+    /// @return 结果
+    ///
+    pub async fn send_msg<T: Into<Vec<Message>>>(&mut self, message_type: String, user_id: i64, group_id: i64, message: T) -> Result<SendMsgResp, BotError> {
+        let resp = self.send_and_wait(Data::SendMsgReq(SendMsgReq {
+            message_type,
+            user_id,
+            group_id,
+            message: message.into(),
+            auto_escape: false,
+        })).await?;
+        if let Data::SendMsgResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -85,14 +256,14 @@ impl Bot {
     /// @param message_id 消息 ID
     /// @return 结果
     ///
-    pub async fn delete_msg(&mut self, message_id: i32) -> Option<DeleteMsgResp> {
+    pub async fn delete_msg(&mut self, message_id: i32) -> Result<DeleteMsgResp, BotError> {
         let resp = self.send_and_wait(Data::DeleteMsgReq(DeleteMsgReq {
             message_id
-        })).await;
-        if let Some(Data::DeleteMsgResp(resp)) = resp {
-            Some(resp)
+        })).await?;
+        if let Data::DeleteMsgResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -102,14 +273,50 @@ impl Bot {
     /// @param message_id 消息 ID
     /// @return 结果
     ///
-    pub async fn get_msg(&mut self, message_id: i32) -> Option<GetMsgResp> {
+    pub async fn get_msg(&mut self, message_id: i32) -> Result<GetMsgResp, BotError> {
         let resp = self.send_and_wait(Data::GetMsgReq(GetMsgReq {
             message_id
-        })).await;
-        if let Some(Data::GetMsgResp(resp)) = resp {
-            Some(resp)
+        })).await?;
+        if let Data::GetMsgResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 获取合并转发消息
+    ///
+    /// @param id 合并转发 ID
+    /// @return 结果
+    ///
+    pub async fn get_forward_msg(&mut self, id: String) -> Result<GetForwardMsgResp, BotError> {
+        let resp = self.send_and_wait(Data::GetForwardMsgReq(GetForwardMsgReq {
+            id,
+        })).await?;
+        if let Data::GetForwardMsgResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 发送好友赞
+    ///
+    /// @param user_id 对方 QQ 号
+    /// @param times   赞的次数，每个好友每天最多 10 次
+    /// @return 结果
+    ///
+    pub async fn send_like(&mut self, user_id: i64, times: i32) -> Result<SendLikeResp, BotError> {
+        let resp = self.send_and_wait(Data::SendLikeReq(SendLikeReq {
+            user_id,
+            times,
+        })).await?;
+        if let Data::SendLikeResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -121,16 +328,16 @@ impl Bot {
     /// @param reject_add_request 拒绝此人的加群请求
     /// @return 结果
     ///
-    pub async fn set_group_kick(&mut self, group_id: i64, user_id: i64, reject_add_request: bool) -> Option<SetGroupKickResp> {
+    pub async fn set_group_kick(&mut self, group_id: i64, user_id: i64, reject_add_request: bool) -> Result<SetGroupKickResp, BotError> {
         let resp = self.send_and_wait(Data::SetGroupKickReq(SetGroupKickReq {
             group_id,
             user_id,
             reject_add_request,
-        })).await;
-        if let Some(Data::SetGroupKickResp(resp)) = resp {
-            Some(resp)
+        })).await?;
+        if let Data::SetGroupKickResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -142,16 +349,38 @@ impl Bot {
     /// @param duration 禁言时长，单位秒，0 表示取消禁言
     /// @return 结果
     ///
-    pub async fn set_group_ban(&mut self, group_id: i64, user_id: i64, duration: i32) -> Option<SetGroupBanResp> {
+    pub async fn set_group_ban(&mut self, group_id: i64, user_id: i64, duration: i32) -> Result<SetGroupBanResp, BotError> {
         let resp = self.send_and_wait(Data::SetGroupBanReq(SetGroupBanReq {
             group_id,
             user_id,
             duration,
-        })).await;
-        if let Some(Data::SetGroupBanResp(resp)) = resp {
-            Some(resp)
+        })).await?;
+        if let Data::SetGroupBanResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 群组匿名用户禁言
+    ///
+    /// @param group_id 群号
+    /// @param flag     要禁言的匿名用户的 flag（需从群消息上报的数据中获得）
+    /// @param duration 禁言时长，单位秒，无法取消匿名用户禁言
+    /// @return 结果
+    ///
+    pub async fn set_group_anonymous_ban(&mut self, group_id: i64, flag: String, duration: i32) -> Result<SetGroupAnonymousBanResp, BotError> {
+        let resp = self.send_and_wait(Data::SetGroupAnonymousBanReq(SetGroupAnonymousBanReq {
+            group_id,
+            anonymous: None,
+            flag,
+            duration,
+        })).await?;
+        if let Data::SetGroupAnonymousBanResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -162,15 +391,55 @@ impl Bot {
     /// @param enable   是否禁言
     /// @return 结果
     ///
-    pub async fn set_group_whole_ban(&mut self, group_id: i64, enable: bool) -> Option<SetGroupWholeBanResp> {
+    pub async fn set_group_whole_ban(&mut self, group_id: i64, enable: bool) -> Result<SetGroupWholeBanResp, BotError> {
         let resp = self.send_and_wait(Data::SetGroupWholeBanReq(SetGroupWholeBanReq {
             group_id,
             enable,
-        })).await;
-        if let Some(Data::SetGroupWholeBanResp(resp)) = resp {
-            Some(resp)
+        })).await?;
+        if let Data::SetGroupWholeBanResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 群组设置管理员
+    ///
+    /// @param group_id 群号
+    /// @param user_id  要设置管理员的 QQ 号
+    /// @param enable   true 为设置，false 为取消
+    /// @return 结果
+    ///
+    pub async fn set_group_admin(&mut self, group_id: i64, user_id: i64, enable: bool) -> Result<SetGroupAdminResp, BotError> {
+        let resp = self.send_and_wait(Data::SetGroupAdminReq(SetGroupAdminReq {
+            group_id,
+            user_id,
+            enable,
+        })).await?;
+        if let Data::SetGroupAdminResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 群组匿名
+    ///
+    /// @param group_id 群号
+    /// @param enable   是否允许匿名聊天
+    /// @return 结果
+    ///
+    pub async fn set_group_anonymous(&mut self, group_id: i64, enable: bool) -> Result<SetGroupAnonymousResp, BotError> {
+        let resp = self.send_and_wait(Data::SetGroupAnonymousReq(SetGroupAnonymousReq {
+            group_id,
+            enable,
+        })).await?;
+        if let Data::SetGroupAnonymousResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -182,16 +451,35 @@ impl Bot {
     /// @param card     群名片内容，空字符串表示删除群名片
     /// @return 结果
     ///
-    pub async fn set_group_card(&mut self, group_id: i64, user_id: i64, card: String) -> Option<SetGroupCardResp> {
+    pub async fn set_group_card(&mut self, group_id: i64, user_id: i64, card: String) -> Result<SetGroupCardResp, BotError> {
         let resp = self.send_and_wait(Data::SetGroupCardReq(SetGroupCardReq {
             group_id,
             user_id,
             card,
-        })).await;
-        if let Some(Data::SetGroupCardResp(resp)) = resp {
-            Some(resp)
+        })).await?;
+        if let Data::SetGroupCardResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 设置群名
+    ///
+    /// @param group_id   群号
+    /// @param group_name 新群名
+    /// @return 结果
+    ///
+    pub async fn set_group_name(&mut self, group_id: i64, group_name: String) -> Result<SetGroupNameResp, BotError> {
+        let resp = self.send_and_wait(Data::SetGroupNameReq(SetGroupNameReq {
+            group_id,
+            group_name,
+        })).await?;
+        if let Data::SetGroupNameResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -202,15 +490,15 @@ impl Bot {
     /// @param is_dismiss 是否解散，如果登录号是群主，则仅在此项为 true 时能够解散
     /// @return 结果
     ///
-    pub async fn set_group_leave(&mut self, group_id: i64, is_dismiss: bool) -> Option<SetGroupLeaveResp> {
+    pub async fn set_group_leave(&mut self, group_id: i64, is_dismiss: bool) -> Result<SetGroupLeaveResp, BotError> {
         let resp = self.send_and_wait(Data::SetGroupLeaveReq(SetGroupLeaveReq {
             group_id,
             is_dismiss,
-        })).await;
-        if let Some(Data::SetGroupLeaveResp(resp)) = resp {
-            Some(resp)
+        })).await?;
+        if let Data::SetGroupLeaveResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -223,17 +511,17 @@ impl Bot {
     /// @param duration      专属头衔有效期，单位秒，-1 表示永久，不过此项似乎没有效果，可能是只有某些特殊的时间长度有效，有待测试
     /// @return 结果
     ///
-    pub async fn set_group_special_title(&mut self, group_id: i64, user_id: i64, special_title: String, duration: i64) -> Option<SetGroupSpecialTitleResp> {
+    pub async fn set_group_special_title(&mut self, group_id: i64, user_id: i64, special_title: String, duration: i64) -> Result<SetGroupSpecialTitleResp, BotError> {
         let resp = self.send_and_wait(Data::SetGroupSpecialTitleReq(SetGroupSpecialTitleReq {
             group_id,
             user_id,
             special_title,
             duration,
-        })).await;
-        if let Some(Data::SetGroupSpecialTitleResp(resp)) = resp {
-            Some(resp)
+        })).await?;
+        if let Data::SetGroupSpecialTitleResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -245,16 +533,16 @@ impl Bot {
     /// @param remark  添加后的好友备注（仅在同意时有效）
     /// @return 结果
     ///
-    pub async fn set_friend_add_request(&mut self, flag: String, approve: bool, remark: String) -> Option<SetFriendAddRequestResp> {
+    pub async fn set_friend_add_request(&mut self, flag: String, approve: bool, remark: String) -> Result<SetFriendAddRequestResp, BotError> {
         let resp = self.send_and_wait(Data::SetFriendAddRequestReq(SetFriendAddRequestReq {
             flag,
             approve,
             remark,
-        })).await;
-        if let Some(Data::SetFriendAddRequestResp(resp)) = resp {
-            Some(resp)
+        })).await?;
+        if let Data::SetFriendAddRequestResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -268,18 +556,18 @@ impl Bot {
     /// @return 结果
     ///
     // TODO r#type 不知道怎么写
-    pub async fn set_group_add_request(&mut self, flag: String, sub_type: String, approve: bool, reason: String) -> Option<SetGroupAddRequestResp> {
+    pub async fn set_group_add_request(&mut self, flag: String, sub_type: String, approve: bool, reason: String) -> Result<SetGroupAddRequestResp, BotError> {
         let resp = self.send_and_wait(Data::SetGroupAddRequestReq(SetGroupAddRequestReq {
             flag,
             sub_type,
             r#type: "".to_string(),
             approve,
             reason,
-        })).await;
-        if let Some(Data::SetGroupAddRequestResp(resp)) = resp {
-            Some(resp)
+        })).await?;
+        if let Data::SetGroupAddRequestResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -288,12 +576,12 @@ impl Bot {
     ///
     /// @return 结果
     ///
-    pub async fn get_login_info(&mut self) -> Option<GetLoginInfoResp> {
-        let resp = self.send_and_wait(Data::GetLoginInfoReq(GetLoginInfoReq {})).await;
-        if let Some(Data::GetLoginInfoResp(resp)) = resp {
-            Some(resp)
+    pub async fn get_login_info(&mut self) -> Result<GetLoginInfoResp, BotError> {
+        let resp = self.send_and_wait(Data::GetLoginInfoReq(GetLoginInfoReq {})).await?;
+        if let Data::GetLoginInfoResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -303,15 +591,15 @@ impl Bot {
     /// @param user_id   QQ号
     /// @return 结果
     ///
-    pub async fn get_stranger_info(&mut self, user_id: i64) -> Option<GetStrangerInfoResp> {
+    pub async fn get_stranger_info(&mut self, user_id: i64) -> Result<GetStrangerInfoResp, BotError> {
         let resp = self.send_and_wait(Data::GetStrangerInfoReq(GetStrangerInfoReq {
             user_id,
             no_cache: false,
-        })).await;
-        if let Some(Data::GetStrangerInfoResp(resp)) = resp {
-            Some(resp)
+        })).await?;
+        if let Data::GetStrangerInfoResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -320,12 +608,12 @@ impl Bot {
     ///
     /// @return 结果
     ///
-    pub async fn get_friend_list(&mut self) -> Option<GetFriendListResp> {
-        let resp = self.send_and_wait(Data::GetFriendListReq(GetFriendListReq {})).await;
-        if let Some(Data::GetFriendListResp(resp)) = resp {
-            Some(resp)
+    pub async fn get_friend_list(&mut self) -> Result<GetFriendListResp, BotError> {
+        let resp = self.send_and_wait(Data::GetFriendListReq(GetFriendListReq {})).await?;
+        if let Data::GetFriendListResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -334,12 +622,12 @@ impl Bot {
     ///
     /// @return 结果
     ///
-    pub async fn get_group_list(&mut self) -> Option<GetGroupListResp> {
-        let resp = self.send_and_wait(Data::GetGroupListReq(GetGroupListReq {})).await;
-        if let Some(Data::GetGroupListResp(resp)) = resp {
-            Some(resp)
+    pub async fn get_group_list(&mut self) -> Result<GetGroupListResp, BotError> {
+        let resp = self.send_and_wait(Data::GetGroupListReq(GetGroupListReq {})).await?;
+        if let Data::GetGroupListResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -350,15 +638,15 @@ impl Bot {
     /// @param no_cache 是否不使用缓存（使用缓存可能更新不及时，但响应更快）
     /// @return 结果
     ///
-    pub async fn get_group_info(&mut self, group_id: i64, no_cache: bool) -> Option<GetGroupInfoResp> {
+    pub async fn get_group_info(&mut self, group_id: i64, no_cache: bool) -> Result<GetGroupInfoResp, BotError> {
         let resp = self.send_and_wait(Data::GetGroupInfoReq(GetGroupInfoReq {
             group_id,
             no_cache,
-        })).await;
-        if let Some(Data::GetGroupInfoResp(resp)) = resp {
-            Some(resp)
+        })).await?;
+        if let Data::GetGroupInfoResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -370,16 +658,16 @@ impl Bot {
     /// @param no_cache 是否不使用缓存（使用缓存可能更新不及时，但响应更快）
     /// @return 结果
     ///
-    pub async fn get_group_member_info(&mut self, group_id: i64, user_id: i64, no_cache: bool) -> Option<GetGroupMemberInfoResp> {
+    pub async fn get_group_member_info(&mut self, group_id: i64, user_id: i64, no_cache: bool) -> Result<GetGroupMemberInfoResp, BotError> {
         let resp = self.send_and_wait(Data::GetGroupMemberInfoReq(GetGroupMemberInfoReq {
             group_id,
             user_id,
             no_cache,
-        })).await;
-        if let Some(Data::GetGroupMemberInfoResp(resp)) = resp {
-            Some(resp)
+        })).await?;
+        if let Data::GetGroupMemberInfoResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 
@@ -392,57 +680,470 @@ impl Bot {
     /// @param group_id 群号
     /// @return 结果
     ///
-    pub async fn get_group_member_list(&mut self, group_id: i64) -> Option<GetGroupListResp> {
-        let resp = self.send_and_wait(Data::GetGroupListReq(GetGroupListReq {})).await;
-        if let Some(Data::GetGroupListResp(resp)) = resp {
-            Some(resp)
+    pub async fn get_group_member_list(&mut self, group_id: i64) -> Result<GetGroupMemberListResp, BotError> {
+        let resp = self.send_and_wait(Data::GetGroupMemberListReq(GetGroupMemberListReq {
+            group_id,
+        })).await?;
+        if let Data::GetGroupMemberListResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 获取群荣誉信息
+    ///
+    /// @param group_id   群号
+    /// @param honor_type 要获取的群荣誉类型，可传入 talkative performer legend strong_newbie emotion 以分别获取单个类型的群荣誉数据，或传入 all 获取所有数据
+    /// @return 结果
+    ///
+    pub async fn get_group_honor_info(&mut self, group_id: i64, honor_type: String) -> Result<GetGroupHonorInfoResp, BotError> {
+        let resp = self.send_and_wait(Data::GetGroupHonorInfoReq(GetGroupHonorInfoReq {
+            group_id,
+            r#type: honor_type,
+        })).await?;
+        if let Data::GetGroupHonorInfoResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 获取 Cookies
+    ///
+    /// @return 结果
+    ///
+    pub async fn get_cookies(&mut self) -> Result<GetCookiesResp, BotError> {
+        let resp = self.send_and_wait(Data::GetCookiesReq(GetCookiesReq {})).await?;
+        if let Data::GetCookiesResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 获取 CSRF Token
+    ///
+    /// @return 结果
+    ///
+    pub async fn get_csrf_token(&mut self) -> Result<GetCsrfTokenResp, BotError> {
+        let resp = self.send_and_wait(Data::GetCsrfTokenReq(GetCsrfTokenReq {})).await?;
+        if let Data::GetCsrfTokenResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 获取 QQ 相关接口凭证
+    ///
+    /// @return 结果
+    ///
+    pub async fn get_credentials(&mut self) -> Result<GetCredentialsResp, BotError> {
+        let resp = self.send_and_wait(Data::GetCredentialsReq(GetCredentialsReq {})).await?;
+        if let Data::GetCredentialsResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 获取语音
+    ///
+    /// @param file       收到的语音文件名
+    /// @param out_format 要转换到的格式，目前支持 mp3、amr、wma、m4a、spx、ogg、wav、flac
+    /// @return 结果
+    ///
+    pub async fn get_record(&mut self, file: String, out_format: String) -> Result<GetRecordResp, BotError> {
+        let resp = self.send_and_wait(Data::GetRecordReq(GetRecordReq {
+            file,
+            out_format,
+        })).await?;
+        if let Data::GetRecordResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 获取图片
+    ///
+    /// @param file 收到的图片文件名
+    /// @return 结果
+    ///
+    pub async fn get_image(&mut self, file: String) -> Result<GetImageResp, BotError> {
+        let resp = self.send_and_wait(Data::GetImageReq(GetImageReq {
+            file,
+        })).await?;
+        if let Data::GetImageResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 检查是否可以发送图片
+    ///
+    /// @return 结果
+    ///
+    pub async fn can_send_image(&mut self) -> Result<CanSendImageResp, BotError> {
+        let resp = self.send_and_wait(Data::CanSendImageReq(CanSendImageReq {})).await?;
+        if let Data::CanSendImageResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 检查是否可以发送语音
+    ///
+    /// @return 结果
+    ///
+    pub async fn can_send_record(&mut self) -> Result<CanSendRecordResp, BotError> {
+        let resp = self.send_and_wait(Data::CanSendRecordReq(CanSendRecordReq {})).await?;
+        if let Data::CanSendRecordResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 获取运行状态
+    ///
+    /// @return 结果
+    ///
+    pub async fn get_status(&mut self) -> Result<GetStatusResp, BotError> {
+        let resp = self.send_and_wait(Data::GetStatusReq(GetStatusReq {})).await?;
+        if let Data::GetStatusResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 获取版本信息
+    ///
+    /// @return 结果
+    ///
+    pub async fn get_version_info(&mut self) -> Result<GetVersionInfoResp, BotError> {
+        let resp = self.send_and_wait(Data::GetVersionInfoReq(GetVersionInfoReq {})).await?;
+        if let Data::GetVersionInfoResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 重启 OneBot 实现
+    ///
+    /// @param delay 要延迟的毫秒数，如果默认情况下无法重启，可以尝试设置延迟为 2000 左右
+    /// @return 结果
+    ///
+    pub async fn set_restart(&mut self, delay: i32) -> Result<SetRestartResp, BotError> {
+        let resp = self.send_and_wait(Data::SetRestartReq(SetRestartReq {
+            delay,
+        })).await?;
+        if let Data::SetRestartResp(resp) = resp {
+            Ok(resp)
+        } else {
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
+        }
+    }
+
+    ///
+    /// 清理缓存
+    ///
+    /// @return 结果
+    ///
+    pub async fn clean_cache(&mut self) -> Result<CleanCacheResp, BotError> {
+        let resp = self.send_and_wait(Data::CleanCacheReq(CleanCacheReq {})).await?;
+        if let Data::CleanCacheResp(resp) = resp {
+            Ok(resp)
         } else {
-            None
+            Err(BotError::UnexpectedData(Some(Box::new(resp))))
         }
     }
 }
 
 
-fn get_frame_type(data: &Data) -> onebot::frame::FrameType {
+/// 请求中要发送的消息
+fn request_messages(data: &Data) -> &[Message] {
     match data {
-        Data::SendPrivateMsgReq(_) => { FrameType::TSendPrivateMsgReq }
-        Data::SendGroupMsgReq(_) => { FrameType::TSendGroupMsgReq }
-        Data::SendMsgReq(_) => { FrameType::TSendGroupMsgReq }
-        Data::DeleteMsgReq(_) => { FrameType::TDeleteMsgReq }
-        Data::GetMsgReq(_) => { FrameType::TGetMsgReq }
-        Data::GetForwardMsgReq(_) => { FrameType::TGetForwardMsgReq }
-        Data::SendLikeReq(_) => { FrameType::TSendLikeReq }
-        Data::SetGroupKickReq(_) => { FrameType::TSetGroupKickReq }
-        Data::SetGroupBanReq(_) => { FrameType::TSetGroupBanReq }
-        Data::SetGroupAnonymousBanReq(_) => { FrameType::TSetGroupAnonymousBanReq }
-        Data::SetGroupWholeBanReq(_) => { FrameType::TSetGroupWholeBanReq }
-        Data::SetGroupAdminReq(_) => { FrameType::TSetGroupAdminReq }
-        Data::SetGroupAnonymousReq(_) => { FrameType::TSetGroupAnonymousReq }
-        Data::SetGroupCardReq(_) => { FrameType::TSetGroupCardReq }
-        Data::SetGroupNameReq(_) => { FrameType::TSetGroupNameReq }
-        Data::SetGroupLeaveReq(_) => { FrameType::TSetGroupLeaveReq }
-        Data::SetGroupSpecialTitleReq(_) => { FrameType::TSetGroupSpecialTitleReq }
-        Data::SetFriendAddRequestReq(_) => { FrameType::TSetFriendAddRequestReq }
-        Data::SetGroupAddRequestReq(_) => { FrameType::TSetGroupAddRequestReq }
-        Data::GetLoginInfoReq(_) => { FrameType::TGetLoginInfoReq }
-        Data::GetStrangerInfoReq(_) => { FrameType::TGetStrangerInfoReq }
-        Data::GetFriendListReq(_) => { FrameType::TGetFriendListReq }
-        Data::GetGroupInfoReq(_) => { FrameType::TGetGroupInfoReq }
-        Data::GetGroupListReq(_) => { FrameType::TGetGroupListReq }
-        Data::GetGroupMemberInfoReq(_) => { FrameType::TGetGroupMemberInfoReq }
-        Data::GetGroupMemberListReq(_) => { FrameType::TGetGroupMemberListReq }
-        Data::GetGroupHonorInfoReq(_) => { FrameType::TGetGroupHonorInfoReq }
-        Data::GetCookiesReq(_) => { FrameType::TGetCookiesReq }
-        Data::GetCsrfTokenReq(_) => { FrameType::TGetCsrfTokenReq }
-        Data::GetCredentialsReq(_) => { FrameType::TGetCredentialsReq }
-        Data::GetRecordReq(_) => { FrameType::TGetRecordReq }
-        Data::GetImageReq(_) => { FrameType::TGetImageReq }
-        Data::CanSendImageReq(_) => { FrameType::TCanSendImageReq }
-        Data::CanSendRecordReq(_) => { FrameType::TCanSendRecordReq }
-        Data::GetStatusReq(_) => { FrameType::TGetStatusReq }
-        Data::GetVersionInfoReq(_) => { FrameType::TGetVersionInfoReq }
-        Data::SetRestartReq(_) => { FrameType::TSetRestartReq }
-        Data::CleanCacheReq(_) => { FrameType::TCleanCacheReq }
-        _ => { FrameType::Tunknown }
-    }
-}
\ No newline at end of file
+        Data::SendPrivateMsgReq(req) => &req.message,
+        Data::SendGroupMsgReq(req) => &req.message,
+        Data::SendMsgReq(req) => &req.message,
+        _ => &[],
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/frame_type.rs"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg;
+
+    /// 调用 get_login_info，对端用 respond 修改响应 Frame
+    async fn login_info_with_response(respond: fn(&mut Frame)) -> Result<GetLoginInfoResp, BotError> {
+        let (api_sender, mut api_receiver) = mpsc::channel(10);
+        let mut bot = Bot::new(10001, api_sender).with_timeout(Duration::from_secs(1));
+        let peer = bot.clone();
+        tokio::spawn(async move {
+            let req = api_receiver.recv().await.unwrap();
+            let mut resp = Frame { echo: req.echo, ok: true, ..Default::default() };
+            respond(&mut resp);
+            peer.handle_response(resp);
+        });
+        bot.get_login_info().await
+    }
+
+    #[tokio::test]
+    async fn error_responses() {
+        let err = login_info_with_response(|resp| {
+            resp.ok = false;
+            resp.extra.insert("msg".to_string(), "failed".to_string());
+        }).await.unwrap_err();
+        assert!(matches!(err, BotError::Remote(extra) if extra["msg"] == "failed"));
+
+        let err = login_info_with_response(|_| {}).await.unwrap_err();
+        assert!(matches!(err, BotError::UnexpectedData(None)));
+
+        let err = login_info_with_response(|resp| {
+            resp.data = Some(Data::SendGroupMsgResp(Default::default()));
+        }).await.unwrap_err();
+        assert!(matches!(err, BotError::UnexpectedData(Some(data)) if matches!(*data, Data::SendGroupMsgResp(_))));
+    }
+
+    #[tokio::test]
+    async fn closed_connection_returns_channel_closed() {
+        // 连接已断开，请求无法发送
+        let (api_sender, api_receiver) = mpsc::channel(10);
+        let mut bot = Bot::new(10001, api_sender);
+        drop(api_receiver);
+        assert!(matches!(bot.get_login_info().await, Err(BotError::ChannelClosed)));
+        assert!(bot.resp_promises.lock().unwrap().is_empty());
+
+        // 请求已发送，连接断开时清空等待中的响应
+        let (api_sender, mut api_receiver) = mpsc::channel(10);
+        let mut bot = Bot::new(10001, api_sender);
+        let peer = bot.clone();
+        tokio::spawn(async move {
+            api_receiver.recv().await.unwrap();
+            peer.resp_promises.lock().unwrap().clear();
+        });
+        assert!(matches!(bot.get_login_info().await, Err(BotError::ChannelClosed)));
+    }
+
+    #[tokio::test]
+    async fn instant_response_is_not_lost() {
+        let (api_sender, mut api_receiver) = mpsc::channel(10);
+        let mut bot = Bot::new(10001, api_sender).with_timeout(Duration::from_secs(1));
+
+        // 模拟对端：收到请求后立即返回响应，此时 echo 必须已经注册
+        let peer = bot.clone();
+        tokio::spawn(async move {
+            while let Some(req) = api_receiver.recv().await {
+                assert!(peer.resp_promises.lock().unwrap().contains_key(&req.echo));
+                assert!(peer.handle_response(Frame {
+                    bot_id: req.bot_id,
+                    frame_type: FrameType::TGetLoginInfoResp.into(),
+                    echo: req.echo,
+                    ok: true,
+                    extra: Default::default(),
+                    data: Some(Data::GetLoginInfoResp(GetLoginInfoResp {
+                        user_id: 10001,
+                        nickname: "bot".to_string(),
+                    })),
+                }));
+            }
+        });
+
+        for _ in 0..100 {
+            let resp = bot.get_login_info().await.unwrap();
+            assert_eq!(resp.user_id, 10001);
+        }
+        assert!(bot.resp_promises.lock().unwrap().is_empty());
+    }
+
+    ///
+    /// 调用 API，检查发出的 Data 和 FrameType 与 $req 一致，并且能正确解析 $resp
+    ///
+    macro_rules! check_api {
+        ($bot:ident => $call:expr, $req:ident, $resp:ident) => {{
+            let (api_sender, mut api_receiver) = mpsc::channel(10);
+            let mut $bot = Bot::new(10001, api_sender).with_timeout(Duration::from_secs(1));
+            let peer = $bot.clone();
+            let call = tokio::spawn(async move { $call.await.map(|_| ()) });
+
+            let req = api_receiver.recv().await.unwrap();
+            let frame_type = FrameType::from_i32(req.frame_type).unwrap();
+            assert_eq!(format!("{:?}", frame_type), concat!("T", stringify!($req)), "{}", stringify!($call));
+            assert!(matches!(req.data, Some(Data::$req(_))), "{}: {:?}", stringify!($call), req.data);
+
+            assert!(peer.handle_response(Frame {
+                echo: req.echo,
+                ok: true,
+                data: Some(Data::$resp(Default::default())),
+                ..Default::default()
+            }));
+            call.await.unwrap().expect(stringify!($call));
+        }};
+    }
+
+    #[tokio::test]
+    async fn api_request_matches_api_name() {
+        check_api!(bot => bot.send_private_message(1, msg::text("a")), SendPrivateMsgReq, SendPrivateMsgResp);
+        check_api!(bot => bot.send_group_message(1, msg::text("a")), SendGroupMsgReq, SendGroupMsgResp);
+        check_api!(bot => bot.send_msg("group".to_string(), 0, 1, msg::text("a")), SendMsgReq, SendMsgResp);
+        check_api!(bot => bot.delete_msg(1), DeleteMsgReq, DeleteMsgResp);
+        check_api!(bot => bot.get_msg(1), GetMsgReq, GetMsgResp);
+        check_api!(bot => bot.set_group_kick(1, 2, false), SetGroupKickReq, SetGroupKickResp);
+        check_api!(bot => bot.set_group_ban(1, 2, 60), SetGroupBanReq, SetGroupBanResp);
+        check_api!(bot => bot.set_group_whole_ban(1, true), SetGroupWholeBanReq, SetGroupWholeBanResp);
+        check_api!(bot => bot.set_group_card(1, 2, "card".to_string()), SetGroupCardReq, SetGroupCardResp);
+        check_api!(bot => bot.set_group_leave(1, false), SetGroupLeaveReq, SetGroupLeaveResp);
+        check_api!(bot => bot.set_group_special_title(1, 2, "title".to_string(), -1), SetGroupSpecialTitleReq, SetGroupSpecialTitleResp);
+        check_api!(bot => bot.set_friend_add_request("flag".to_string(), true, "".to_string()), SetFriendAddRequestReq, SetFriendAddRequestResp);
+        check_api!(bot => bot.set_group_add_request("flag".to_string(), "add".to_string(), true, "".to_string()), SetGroupAddRequestReq, SetGroupAddRequestResp);
+        check_api!(bot => bot.get_login_info(), GetLoginInfoReq, GetLoginInfoResp);
+        check_api!(bot => bot.get_stranger_info(1), GetStrangerInfoReq, GetStrangerInfoResp);
+        check_api!(bot => bot.get_friend_list(), GetFriendListReq, GetFriendListResp);
+        check_api!(bot => bot.get_group_list(), GetGroupListReq, GetGroupListResp);
+        check_api!(bot => bot.get_group_info(1, false), GetGroupInfoReq, GetGroupInfoResp);
+        check_api!(bot => bot.get_group_member_info(1, 2, false), GetGroupMemberInfoReq, GetGroupMemberInfoResp);
+        check_api!(bot => bot.get_group_member_list(1), GetGroupMemberListReq, GetGroupMemberListResp);
+    }
+
+    #[tokio::test]
+    async fn remaining_api_request_matches_api_name() {
+        check_api!(bot => bot.get_forward_msg("id".to_string()), GetForwardMsgReq, GetForwardMsgResp);
+        check_api!(bot => bot.send_like(1, 10), SendLikeReq, SendLikeResp);
+        check_api!(bot => bot.set_group_anonymous_ban(1, "flag".to_string(), 60), SetGroupAnonymousBanReq, SetGroupAnonymousBanResp);
+        check_api!(bot => bot.set_group_admin(1, 2, true), SetGroupAdminReq, SetGroupAdminResp);
+        check_api!(bot => bot.set_group_anonymous(1, true), SetGroupAnonymousReq, SetGroupAnonymousResp);
+        check_api!(bot => bot.set_group_name(1, "name".to_string()), SetGroupNameReq, SetGroupNameResp);
+        check_api!(bot => bot.get_group_honor_info(1, "all".to_string()), GetGroupHonorInfoReq, GetGroupHonorInfoResp);
+        check_api!(bot => bot.get_cookies(), GetCookiesReq, GetCookiesResp);
+        check_api!(bot => bot.get_csrf_token(), GetCsrfTokenReq, GetCsrfTokenResp);
+        check_api!(bot => bot.get_credentials(), GetCredentialsReq, GetCredentialsResp);
+        check_api!(bot => bot.get_record("file".to_string(), "mp3".to_string()), GetRecordReq, GetRecordResp);
+        check_api!(bot => bot.get_image("file".to_string()), GetImageReq, GetImageResp);
+        check_api!(bot => bot.can_send_image(), CanSendImageReq, CanSendImageResp);
+        check_api!(bot => bot.can_send_record(), CanSendRecordReq, CanSendRecordResp);
+        check_api!(bot => bot.get_status(), GetStatusReq, GetStatusResp);
+        check_api!(bot => bot.get_version_info(), GetVersionInfoReq, GetVersionInfoResp);
+        check_api!(bot => bot.set_restart(2000), SetRestartReq, SetRestartResp);
+        check_api!(bot => bot.clean_cache(), CleanCacheReq, CleanCacheResp);
+    }
+
+    #[test]
+    fn frame_type_matches_every_request() {
+        // onebot_frame.proto 中 Frame.data 的字段号和对应的 FrameType 相同，用字段号构造每个请求的 Data
+        let mut requests = 0;
+        for value in 0..1000 {
+            let frame_type = match FrameType::from_i32(value) {
+                Some(frame_type) if format!("{:?}", frame_type).ends_with("Req") => frame_type,
+                _ => continue,
+            };
+            let mut buf = Vec::new();
+            prost::encoding::encode_key(value as u32, prost::encoding::WireType::LengthDelimited, &mut buf);
+            prost::encoding::encode_varint(0, &mut buf);
+            let frame = <Frame as prost::Message>::decode(buf.as_slice()).unwrap();
+            let data = frame.data.unwrap_or_else(|| panic!("{:?} has no Frame.data variant", frame_type));
+            assert!(format!("T{:?}", data).starts_with(&format!("{:?}(", frame_type)), "{:?}: {:?}", frame_type, data);
+            assert_eq!(get_frame_type(&data), frame_type);
+            requests += 1;
+        }
+        assert!(requests > 0);
+        assert_eq!(get_frame_type(&Data::SendMsgReq(Default::default())), FrameType::TSendMsgReq);
+    }
+
+    #[tokio::test]
+    async fn strict_mode_rejects_invalid_segment() {
+        let (api_sender, mut api_receiver) = mpsc::channel(10);
+        let mut bot = Bot::new(10001, api_sender).with_strict(true);
+
+        let invalid = Message { r#type: "at".to_string(), data: Default::default() };
+        let err = bot.send_group_message(1, msg::text("a") + invalid).await.unwrap_err();
+        assert!(matches!(err, BotError::InvalidSegment(errors) if errors.len() == 1));
+        drop(bot);
+        assert!(api_receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn strict_mode_accepts_spec_segments() {
+        let (api_sender, mut api_receiver) = mpsc::channel(10);
+        let mut bot = Bot::new(10001, api_sender).with_strict(true);
+        let segment = |r#type: &str, data: &[(&str, &str)]| Message {
+            r#type: r#type.to_string(),
+            data: data.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+        };
+        let message = vec![
+            segment("music", &[("type", "163"), ("id", "28949129")]),
+            segment("location", &[("lat", "39.8969426"), ("lon", "116.3109099"), ("title", "title")]),
+            segment("dice", &[]),
+        ];
+
+        let peer = bot.clone();
+        tokio::spawn(async move {
+            let req = api_receiver.recv().await.unwrap();
+            peer.handle_response(Frame {
+                echo: req.echo,
+                ok: true,
+                data: Some(Data::SendGroupMsgResp(SendGroupMsgResp { message_id: 1 })),
+                ..Default::default()
+            });
+        });
+        assert_eq!(bot.send_group_message(1, message).await.unwrap().message_id, 1);
+    }
+
+    #[tokio::test]
+    async fn full_api_channel_times_out() {
+        // 发送队列已满且没有被读取
+        let (api_sender, _api_receiver) = mpsc::channel(1);
+        api_sender.send(Frame::default()).await.unwrap();
+        let mut bot = Bot::new(10001, api_sender).with_timeout(Duration::from_millis(10));
+
+        let err = bot.get_login_info().await.unwrap_err();
+        assert!(matches!(err, BotError::Timeout));
+        assert!(bot.resp_promises.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn timeout_removes_pending_echo() {
+        let (api_sender, _api_receiver) = mpsc::channel(10);
+        let mut bot = Bot::new(10001, api_sender).with_timeout(Duration::from_millis(10));
+
+        let err = bot.get_login_info().await.unwrap_err();
+        assert!(matches!(err, BotError::Timeout));
+        assert!(bot.resp_promises.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn dropped_call_removes_pending_echo() {
+        let (api_sender, mut api_receiver) = mpsc::channel(10);
+        let bot = Bot::new(10001, api_sender);
+        let call = tokio::spawn({
+            let mut bot = bot.clone();
+            async move { bot.get_login_info().await }
+        });
+
+        // 请求发出后 echo 已经注册，取消调用后被移除
+        let req = api_receiver.recv().await.unwrap();
+        assert!(bot.resp_promises.lock().unwrap().contains_key(&req.echo));
+        call.abort();
+        assert!(call.await.unwrap_err().is_cancelled());
+        assert!(bot.resp_promises.lock().unwrap().is_empty());
+    }
+}
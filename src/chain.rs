@@ -0,0 +1,154 @@
+use crate::onebot::Message;
+use std::ops::Deref;
+
+///
+/// 消息链，方便从收到的消息中取出文本、@、回复、图片等信息
+///
+/// let chain = MessageChain::from(event.message.clone());
+/// if let Some(command) = chain.strip_leading_at(event.self_id) {
+///     println!("{}", command.plain_text());
+/// }
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MessageChain(pub Vec<Message>);
+
+impl MessageChain {
+    /// 所有文本消息段拼接起来的纯文本
+    pub fn plain_text(&self) -> String {
+        self.0.iter()
+            .filter(|message| message.r#type == "text")
+            .filter_map(|message| message.data.get("text"))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// 被 @ 的 QQ 号，不包括 @全体成员
+    pub fn mentions(&self) -> Vec<i64> {
+        self.0.iter().filter_map(at_qq).collect()
+    }
+
+    /// 是否 @ 了 bot_id
+    pub fn is_at(&self, bot_id: i64) -> bool {
+        self.0.iter().any(|message| at_qq(message) == Some(bot_id))
+    }
+
+    /// 是否 @全体成员
+    pub fn at_all(&self) -> bool {
+        self.0.iter().any(|message| message.r#type == "at" && message.data.get("qq").map(String::as_str) == Some("all"))
+    }
+
+    /// 回复的消息 ID
+    pub fn reply_to(&self) -> Option<i32> {
+        self.0.iter()
+            .filter(|message| message.r#type == "reply")
+            .find_map(|message| message.data.get("message_id")?.parse().ok())
+    }
+
+    /// 图片地址，没有 url 时使用 file
+    pub fn images(&self) -> Vec<&str> {
+        self.0.iter()
+            .filter(|message| message.r#type == "image")
+            .filter_map(|message| message.data.get("url").or_else(|| message.data.get("file")))
+            .map(String::as_str)
+            .collect()
+    }
+
+    ///
+    /// 去掉开头的 @bot_id，以及紧随其后文本的前导空白
+    ///
+    /// 开头的回复消息段会被保留，@ 之前的空白文本会被去掉
+    ///
+    /// @param bot_id 机器人 QQ 号
+    /// @return 不是以 @bot_id 开头时返回 None
+    ///
+    pub fn strip_leading_at(&self, bot_id: i64) -> Option<MessageChain> {
+        let at_index = self.0.iter().position(|message| !is_reply_or_blank(message))?;
+        if at_qq(&self.0[at_index]) != Some(bot_id) {
+            return None;
+        }
+        let mut messages: Vec<Message> = self.0[..at_index].iter()
+            .filter(|message| message.r#type == "reply")
+            .cloned()
+            .collect();
+        let text_index = messages.len();
+        messages.extend_from_slice(&self.0[at_index + 1..]);
+        if let Some(text) = messages.get_mut(text_index)
+            .filter(|message| message.r#type == "text")
+            .and_then(|message| message.data.get_mut("text")) {
+            *text = text.trim_start().to_string();
+        }
+        Some(MessageChain(messages))
+    }
+}
+
+fn at_qq(message: &Message) -> Option<i64> {
+    if message.r#type == "at" {
+        message.data.get("qq")?.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn is_reply_or_blank(message: &Message) -> bool {
+    match message.r#type.as_str() {
+        "reply" => true,
+        "text" => message.data.get("text").map(|text| text.trim().is_empty()).unwrap_or(true),
+        _ => false,
+    }
+}
+
+impl Deref for MessageChain {
+    type Target = Vec<Message>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<Vec<Message>> for MessageChain {
+    fn from(messages: Vec<Message>) -> Self {
+        MessageChain(messages)
+    }
+}
+
+impl From<&[Message]> for MessageChain {
+    fn from(messages: &[Message]) -> Self {
+        MessageChain(messages.to_vec())
+    }
+}
+
+impl From<MessageChain> for Vec<Message> {
+    fn from(chain: MessageChain) -> Self {
+        chain.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::*;
+
+    #[test]
+    fn inspect() {
+        let chain = MessageChain::from(reply(7) + at(10001) + text(" roll ") + at(20002) + image("http://a/b.png") + text("d6") + at_all());
+        assert_eq!(chain.plain_text(), " roll d6");
+        assert_eq!(chain.mentions(), vec![10001, 20002]);
+        assert!(chain.is_at(20002));
+        assert!(!chain.is_at(30003));
+        assert!(chain.at_all());
+        assert_eq!(chain.reply_to(), Some(7));
+        assert_eq!(chain.images(), vec!["http://a/b.png"]);
+    }
+
+    #[test]
+    fn strip_leading_at() {
+        let chain = MessageChain::from(reply(7) + text(" ") + at(10001) + text("  roll d6"));
+        let stripped = chain.strip_leading_at(10001).unwrap();
+        assert_eq!(stripped.0, reply(7) + text("roll d6"));
+        assert_eq!(stripped.plain_text(), "roll d6");
+
+        assert_eq!(chain.strip_leading_at(20002), None);
+        assert_eq!(MessageChain::from(text("hi ") + at(10001)).strip_leading_at(10001), None);
+        assert_eq!(MessageChain::default().strip_leading_at(10001), None);
+    }
+}
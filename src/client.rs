@@ -0,0 +1,356 @@
+use crate::codec::Codec;
+use crate::connection;
+use crate::options::{BotBuilder, BotOptions};
+use crate::shutdown;
+use axum::extract::ws::{CloseFrame, Message};
+use axum::http::header::AUTHORIZATION;
+use axum::http::HeaderValue;
+use futures::{future, Sink, SinkExt, Stream, TryStreamExt};
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest, handshake::client::Request};
+use tracing::Instrument;
+
+/// 默认第一次重连前等待的时间
+const DEFAULT_RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 默认最长重连间隔
+const DEFAULT_MAX_RECONNECT_INTERVAL: Duration = Duration::from_secs(60);
+
+///
+/// 正向 websocket 客户端，主动连接到 Go-Mirai-Client 的 websocket 服务
+///
+/// 和 BotServer 使用相同的 Bot 和事件循环，事件处理器、中间件和退出等配置通过 BotBuilder 设置，
+/// 连接断开后按指数退避自动重连
+///
+/// BotClient::new("ws://127.0.0.1:8080/ws", 10001)
+///     .access_token("token")
+///     .handler(MyHandler)
+///     .run()
+///     .await
+///
+pub struct BotClient {
+    url: String,
+    self_id: i64,
+    access_token: Option<String>,
+    codec: Codec,
+    options: BotOptions,
+    reconnect_interval: Duration,
+    max_reconnect_interval: Duration,
+}
+
+impl BotClient {
+    ///
+    /// @param url     websocket 地址，例如 ws://127.0.0.1:8080/ws
+    /// @param self_id 机器人 QQ 号，通过 x-self-id 头发送
+    ///
+    pub fn new(url: &str, self_id: i64) -> BotClient {
+        BotClient {
+            url: url.to_string(),
+            self_id,
+            access_token: None,
+            codec: Codec::Protobuf,
+            options: BotOptions::default(),
+            reconnect_interval: DEFAULT_RECONNECT_INTERVAL,
+            max_reconnect_interval: DEFAULT_MAX_RECONNECT_INTERVAL,
+        }
+    }
+
+    /// 连接时通过 Authorization: Bearer 头发送的 token
+    pub fn access_token(mut self, token: &str) -> BotClient {
+        self.access_token = Some(token.to_string());
+        self
+    }
+
+    /// 连接使用的编码，默认 Protobuf
+    pub fn codec(mut self, codec: Codec) -> BotClient {
+        self.codec = codec;
+        self
+    }
+
+    ///
+    /// 重连间隔，每次连接失败或断开后翻倍，连接保持超过 max 后断开时恢复
+    ///
+    /// @param interval 第一次重连前等待的时间，默认 1 秒
+    /// @param max      最长等待时间，默认 60 秒
+    ///
+    pub fn reconnect_interval(mut self, interval: Duration, max: Duration) -> BotClient {
+        self.reconnect_interval = interval;
+        self.max_reconnect_interval = max.max(interval);
+        self
+    }
+
+    /// 连接请求，带有 x-self-id 和 Authorization 头
+    #[allow(clippy::result_large_err)]
+    fn request(&self) -> Result<Request, tungstenite::Error> {
+        let mut request = self.url.as_str().into_client_request()?;
+        let headers = request.headers_mut();
+        headers.insert("x-self-id", HeaderValue::from(self.self_id));
+        if let Some(token) = &self.access_token {
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", token))?);
+        }
+        Ok(request)
+    }
+
+    ///
+    /// 连接并处理事件，断开后自动重连，直到 shutdown_signal 完成
+    ///
+    /// @return url 或 token 不合法时立即返回错误，连接失败不会返回
+    ///
+    pub async fn run(mut self) -> Result<(), tungstenite::Error> {
+        self.request()?;
+        let (config, shutdown) = std::mem::take(&mut self.options).start();
+        let mut shutdown_receiver = config.shutdown.clone();
+        let span = tracing::info_span!("connection", bot_id = self.self_id, url = %self.url);
+        let mut backoff = Backoff::new(self.reconnect_interval, self.max_reconnect_interval);
+        let connect_loop = async move {
+            loop {
+                let connected = tokio::select! {
+                    connected = tokio_tungstenite::connect_async(self.request()?) => connected,
+                    _ = shutdown::deadline(&mut shutdown_receiver) => break,
+                };
+                match connected {
+                    Ok((stream, _)) => {
+                        let connected_at = Instant::now();
+                        connection::run(adapt(stream), self.self_id, config.clone(), Some(self.codec)).await;
+                        backoff.disconnected(connected_at.elapsed());
+                    }
+                    Err(err) => tracing::warn!(error = %err, "failed to connect"),
+                }
+                if shutdown_receiver.borrow().is_some() {
+                    break;
+                }
+                let delay = backoff.next();
+                tracing::info!(delay_ms = delay.as_millis() as u64, "reconnecting");
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = shutdown::deadline(&mut shutdown_receiver) => break,
+                }
+            }
+            Ok(())
+        }.instrument(span);
+
+        tokio::pin!(connect_loop, shutdown);
+        tokio::select! {
+            result = &mut connect_loop => return result,
+            _ = &mut shutdown => {}
+        }
+        // 已经通知连接退出，等待连接关闭
+        connect_loop.await
+    }
+}
+
+impl BotBuilder for BotClient {
+    fn options_mut(&mut self) -> &mut BotOptions {
+        &mut self.options
+    }
+}
+
+/// 把 tungstenite 的连接转换为 axum 的 Message，和 BotServer 共用事件循环
+fn adapt<S>(stream: S) -> impl Stream<Item = Result<Message, tungstenite::Error>> + Sink<Message, Error = tungstenite::Error>
+where
+    S: Stream<Item = Result<tungstenite::Message, tungstenite::Error>> + Sink<tungstenite::Message, Error = tungstenite::Error>,
+{
+    stream
+        .with(|ws_message| future::ready(Ok::<_, tungstenite::Error>(into_tungstenite(ws_message))))
+        .map_ok(from_tungstenite)
+}
+
+fn into_tungstenite(ws_message: Message) -> tungstenite::Message {
+    match ws_message {
+        Message::Text(text) => tungstenite::Message::Text(text),
+        Message::Binary(buf) => tungstenite::Message::Binary(buf),
+        Message::Ping(buf) => tungstenite::Message::Ping(buf),
+        Message::Pong(buf) => tungstenite::Message::Pong(buf),
+        Message::Close(close_frame) => tungstenite::Message::Close(close_frame.map(|close_frame| {
+            tungstenite::protocol::CloseFrame { code: close_frame.code.into(), reason: close_frame.reason }
+        })),
+    }
+}
+
+fn from_tungstenite(ws_message: tungstenite::Message) -> Message {
+    match ws_message {
+        tungstenite::Message::Text(text) => Message::Text(text),
+        tungstenite::Message::Binary(buf) => Message::Binary(buf),
+        tungstenite::Message::Ping(buf) => Message::Ping(buf),
+        tungstenite::Message::Pong(buf) => Message::Pong(buf),
+        tungstenite::Message::Close(close_frame) => Message::Close(close_frame.map(|close_frame| {
+            CloseFrame { code: close_frame.code.into(), reason: close_frame.reason }
+        })),
+    }
+}
+
+///
+/// 指数退避，每次取值后间隔翻倍，直到 max
+///
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff { initial, max, current: initial }
+    }
+
+    fn next(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    /// 连接断开，保持超过 max 时恢复初始间隔，对端接受连接后立即断开时继续退避
+    fn disconnected(&mut self, uptime: Duration) {
+        if uptime >= self.max {
+            self.current = self.initial;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::Bot;
+    use crate::context::EventContext;
+    use crate::handler::{EventHandler, Propagation};
+    use crate::onebot::frame::Data;
+    use crate::onebot::*;
+    use crate::registry::BotRegistry;
+    use crate::test_util::{binary, free_port, private_message};
+    use async_trait::async_trait;
+    use futures::StreamExt;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+    use tokio::sync::{mpsc, oneshot};
+    use tokio_tungstenite::tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+
+    #[test]
+    fn backoff_doubles_until_max_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+        let delays: Vec<u64> = (0..5).map(|_| backoff.next().as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+        backoff.disconnected(Duration::from_secs(4));
+        assert_eq!(backoff.next(), Duration::from_secs(5));
+        backoff.disconnected(Duration::from_secs(5));
+        assert_eq!(backoff.next(), Duration::from_secs(1));
+    }
+
+    struct Nickname(mpsc::Sender<String>);
+
+    #[async_trait]
+    impl EventHandler for Nickname {
+        async fn on_private_message(&self, ctx: &EventContext<PrivateMessageEvent>) -> Propagation {
+            let mut bot: Bot = ctx.bot.clone();
+            if let Ok(resp) = bot.get_login_info().await {
+                let _ = self.0.send(resp.nickname).await;
+            }
+            Propagation::Continue
+        }
+    }
+
+    /// 记录握手请求中的 x-self-id 和 Authorization 头
+    struct RecordHeaders(Arc<Mutex<Vec<(String, String)>>>);
+
+    impl Callback for RecordHeaders {
+        fn on_request(self, req: &Request, resp: Response) -> Result<Response, ErrorResponse> {
+            let header = |name| req.headers().get(name).unwrap().to_str().unwrap().to_string();
+            self.0.lock().unwrap().push((header("x-self-id"), header("authorization")));
+            Ok(resp)
+        }
+    }
+
+    /// 模拟 Go-Mirai-Client 的 websocket 服务，每个连接发送一个事件并回答 get_login_info
+    async fn stand_in_server(listener: TcpListener, headers: Arc<Mutex<Vec<(String, String)>>>, connections: usize) {
+        for connection in 0..connections {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let mut stream = tokio_tungstenite::accept_hdr_async(tcp, RecordHeaders(headers.clone())).await.unwrap();
+
+            stream.send(private_message()).await.unwrap();
+            let req = match stream.next().await.unwrap().unwrap() {
+                tungstenite::Message::Binary(buf) => <Frame as prost::Message>::decode(buf.as_ref()).unwrap(),
+                other => panic!("unexpected message {:?}", other),
+            };
+            assert!(matches!(req.data, Some(Data::GetLoginInfoReq(_))));
+            stream.send(binary(Frame {
+                echo: req.echo,
+                ok: true,
+                data: Some(Data::GetLoginInfoResp(GetLoginInfoResp { user_id: 10001, nickname: connection.to_string() })),
+                ..Default::default()
+            })).await.unwrap();
+
+            if connection + 1 < connections {
+                // 断开后客户端重连
+                stream.close(None).await.unwrap();
+            } else {
+                // 等待客户端退出时发送的 Close
+                match stream.next().await.unwrap().unwrap() {
+                    tungstenite::Message::Close(Some(frame)) => assert_eq!(u16::from(frame.code), 1001),
+                    other => panic!("unexpected message {:?}", other),
+                }
+                while let Some(Ok(_)) = stream.next().await {}
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnects_and_shuts_down() {
+        // 先占用端口拿到地址，客户端第一次连接失败后再开始监听
+        let port = free_port();
+        let (nicknames_sender, mut nicknames) = mpsc::channel(10);
+        let (signal_sender, signal_receiver) = oneshot::channel::<()>();
+        let registry = BotRegistry::new();
+        let client = tokio::spawn(BotClient::new(&format!("ws://127.0.0.1:{}/ws", port), 10001)
+            .access_token("secret")
+            .codec(Codec::Protobuf)
+            .registry(registry.clone())
+            .handler(Nickname(nicknames_sender))
+            .reconnect_interval(Duration::from_millis(20), Duration::from_millis(40))
+            .shutdown_signal(async {
+                let _ = signal_receiver.await;
+            })
+            .run());
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let headers = Arc::new(Mutex::new(Vec::new()));
+        let server = tokio::spawn(stand_in_server(TcpListener::bind(("127.0.0.1", port)).await.unwrap(), headers.clone(), 2));
+
+        assert_eq!(nicknames.recv().await.unwrap(), "0");
+        assert_eq!(nicknames.recv().await.unwrap(), "1");
+        assert!(registry.is_online(10001));
+        signal_sender.send(()).unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), client).await.unwrap().unwrap().unwrap();
+        server.await.unwrap();
+        assert!(!registry.is_online(10001));
+        assert_eq!(*headers.lock().unwrap(), vec![("10001".to_string(), "Bearer secret".to_string()); 2]);
+    }
+
+    #[tokio::test]
+    async fn backs_off_when_server_drops_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (_signal_sender, signal_receiver) = oneshot::channel::<()>();
+        tokio::spawn(BotClient::new(&format!("ws://127.0.0.1:{}/ws", port), 10001)
+            .reconnect_interval(Duration::from_millis(20), Duration::from_secs(1))
+            .shutdown_signal(async {
+                let _ = signal_receiver.await;
+            })
+            .run());
+
+        // 握手成功后立即断开，重连间隔仍然翻倍：20ms、40ms、80ms
+        let mut accepted = Vec::new();
+        for _ in 0..4 {
+            let (tcp, _) = listener.accept().await.unwrap();
+            accepted.push(Instant::now());
+            let mut stream = tokio_tungstenite::accept_async(tcp).await.unwrap();
+            stream.close(None).await.unwrap();
+        }
+        assert!(accepted[3] - accepted[2] >= Duration::from_millis(80));
+    }
+
+    #[tokio::test]
+    async fn invalid_url_returns_error() {
+        assert!(BotClient::new("not a url", 10001).run().await.is_err());
+    }
+}
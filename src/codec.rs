@@ -0,0 +1,151 @@
+use crate::error::BotError;
+use crate::onebot::Frame;
+use axum::extract::ws::Message;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+///
+/// websocket 上 Frame 的编码方式
+///
+/// Protobuf 使用 Binary 消息；Json 使用 Text 消息，字段名和 proto 中一致，
+/// data 为 {"private_message_event": {...}} 的形式，frame_type 为整数
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Protobuf,
+    Json,
+}
+
+impl Codec {
+    /// 根据 websocket 消息类型判断编码，不是 Binary 或 Text 时返回 None
+    pub fn detect(ws_message: &Message) -> Option<Codec> {
+        match ws_message {
+            Message::Binary(_) => Some(Codec::Protobuf),
+            Message::Text(_) => Some(Codec::Json),
+            _ => None,
+        }
+    }
+
+    pub fn encode(self, frame: &Frame) -> Result<Message, BotError> {
+        match self {
+            Codec::Protobuf => {
+                let mut buf = Vec::new();
+                prost::Message::encode(frame, &mut buf).map_err(BotError::Encode)?;
+                Ok(Message::Binary(buf))
+            }
+            Codec::Json => Ok(Message::Text(serde_json::to_string(frame)?)),
+        }
+    }
+
+    /// 解码 Binary 或 Text 消息的内容
+    pub fn decode(self, buf: &[u8]) -> Result<Frame, BotError> {
+        match self {
+            Codec::Protobuf => Ok(prost::Message::decode(buf)?),
+            Codec::Json => Ok(serde_json::from_slice(buf)?),
+        }
+    }
+}
+
+///
+/// 一个连接的编码，没有指定时由收到的第一个 Frame 决定
+///
+/// 确定之前不能发送 Frame，否则只支持 JSON 的客户端会收到无法解析的 Binary 消息
+///
+#[derive(Clone)]
+pub(crate) struct ConnectionCodec {
+    sender: Arc<watch::Sender<Option<Codec>>>,
+    receiver: watch::Receiver<Option<Codec>>,
+}
+
+impl ConnectionCodec {
+    pub(crate) fn new(codec: Option<Codec>) -> ConnectionCodec {
+        let (sender, receiver) = watch::channel(codec);
+        ConnectionCodec { sender: Arc::new(sender), receiver }
+    }
+
+    /// 等待编码确定
+    pub(crate) async fn known(&mut self) -> Codec {
+        loop {
+            if let Some(codec) = *self.receiver.borrow() {
+                return codec;
+            }
+            // sender 和 receiver 在同一个 ConnectionCodec 中，changed 不会因为 sender 被 drop 而返回错误
+            let _ = self.receiver.changed().await;
+        }
+    }
+
+    /// 收到 received 编码的消息，还没有确定时使用 received，返回连接的编码，只在接收消息的 task 中调用
+    pub(crate) fn detect(&self, received: Codec) -> Codec {
+        if let Some(codec) = *self.receiver.borrow() {
+            return codec;
+        }
+        let _ = self.sender.send(Some(received));
+        received
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::text;
+    use crate::onebot::frame::{Data, FrameType};
+    use crate::onebot::{PrivateMessageEvent, SendGroupMsgReq};
+
+    fn frame() -> Frame {
+        Frame {
+            bot_id: 10001,
+            frame_type: FrameType::TSendGroupMsgReq.into(),
+            echo: "1".to_string(),
+            ok: true,
+            data: Some(Data::SendGroupMsgReq(SendGroupMsgReq {
+                group_id: 100,
+                message: vec![text("hi")],
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for codec in [Codec::Protobuf, Codec::Json] {
+            let ws_message = codec.encode(&frame()).unwrap();
+            assert_eq!(Codec::detect(&ws_message), Some(codec));
+            let buf = match ws_message {
+                Message::Binary(buf) => buf,
+                Message::Text(text) => text.into_bytes(),
+                _ => unreachable!(),
+            };
+            assert_eq!(codec.decode(&buf).unwrap(), frame());
+        }
+    }
+
+    #[test]
+    fn json_missing_fields_use_default() {
+        let json = r#"{"bot_id":10001,"data":{"private_message_event":{"user_id":1,"message":[{"type":"text","data":{"text":"hi"}}]}}}"#;
+        let frame = Codec::Json.decode(json.as_bytes()).unwrap();
+        assert_eq!(frame.data, Some(Data::PrivateMessageEvent(PrivateMessageEvent {
+            user_id: 1,
+            message: vec![text("hi")],
+            ..Default::default()
+        })));
+        assert!(matches!(Codec::Json.decode(b"{\"data\": 1}"), Err(BotError::Json(_))));
+    }
+
+    #[tokio::test]
+    async fn connection_codec_detects_first_frame() {
+        let codec = ConnectionCodec::new(None);
+        let mut waiting = tokio::spawn({
+            let mut codec = codec.clone();
+            async move { codec.known().await }
+        });
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(10), &mut waiting).await.is_err());
+        assert_eq!(codec.detect(Codec::Json), Codec::Json);
+        assert_eq!(codec.detect(Codec::Protobuf), Codec::Json);
+        assert_eq!(waiting.await.unwrap(), Codec::Json);
+
+        let mut fixed = ConnectionCodec::new(Some(Codec::Protobuf));
+        assert_eq!(fixed.detect(Codec::Json), Codec::Protobuf);
+        assert_eq!(fixed.known().await, Codec::Protobuf);
+    }
+}
@@ -0,0 +1,595 @@
+use crate::bot::Bot;
+use crate::chain::MessageChain;
+use crate::context::EventContext;
+use crate::handler::{EventHandler, Propagation};
+use crate::msg;
+use crate::onebot::*;
+use async_trait::async_trait;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+
+/// 参数类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    /// 整数
+    Int,
+    /// QQ 号，@某人 或者直接输入数字
+    At,
+    /// 一个单词，或者用双引号括起来的字符串
+    Str,
+    /// 剩余的全部内容，只能是最后一个参数
+    Rest,
+}
+
+/// 解析后的参数值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Arg {
+    Int(i64),
+    At(i64),
+    Str(String),
+}
+
+/// 参数定义
+#[derive(Debug, Clone)]
+struct ArgSpec {
+    name: String,
+    kind: ArgKind,
+    optional: bool,
+}
+
+impl fmt::Display for ArgSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (open, close) = if self.optional { ('[', ']') } else { ('<', '>') };
+        match self.kind {
+            ArgKind::Int => write!(f, "{}{}:int{}", open, self.name, close),
+            ArgKind::At => write!(f, "{}{}:@{}", open, self.name, close),
+            ArgKind::Str => write!(f, "{}{}{}", open, self.name, close),
+            ArgKind::Rest => write!(f, "{}{}...{}", open, self.name, close),
+        }
+    }
+}
+
+/// 参数解析失败原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgError {
+    Missing(String),
+    Invalid { name: String, value: String },
+    TooMany,
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgError::Missing(name) => write!(f, "缺少参数 {}", name),
+            ArgError::Invalid { name, value } => write!(f, "参数 {} 的值 {} 不正确", name, value),
+            ArgError::TooMany => write!(f, "参数过多"),
+        }
+    }
+}
+
+/// 按名字获取参数
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Args {
+    values: Vec<(String, Arg)>,
+}
+
+impl Args {
+    pub fn get(&self, name: &str) -> Option<&Arg> {
+        self.values.iter().find(|(n, _)| n == name).map(|(_, arg)| arg)
+    }
+
+    pub fn int(&self, name: &str) -> Option<i64> {
+        match self.get(name)? {
+            Arg::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn at(&self, name: &str) -> Option<i64> {
+        match self.get(name)? {
+            Arg::At(qq) => Some(*qq),
+            _ => None,
+        }
+    }
+
+    /// Str 和 Rest 参数
+    pub fn str(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            Arg::Str(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// 一次命令调用
+#[derive(Debug, Clone)]
+pub struct CommandCall {
+    /// 命令名，使用别名调用时也是命令名
+    pub name: String,
+    pub args: Args,
+    pub user_id: i64,
+    /// 私聊时为 None
+    pub group_id: Option<i64>,
+    pub message_id: i32,
+}
+
+/// 命令处理函数，可以直接使用 async 闭包 |bot, call| async move { ... }
+#[async_trait]
+pub trait CommandHandler: Send + Sync {
+    async fn handle(&self, bot: Bot, call: CommandCall);
+}
+
+#[async_trait]
+impl<F, Fut> CommandHandler for F
+where
+    F: Fn(Bot, CommandCall) -> Fut + Send + Sync,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    async fn handle(&self, bot: Bot, call: CommandCall) {
+        self(bot, call).await
+    }
+}
+
+///
+/// 命令定义
+///
+/// Command::new("roll", roll)
+///     .alias("r")
+///     .description("掷骰子")
+///     .arg("sides", ArgKind::Int)
+///     .optional_arg("count", ArgKind::Int)
+///
+#[derive(Clone)]
+pub struct Command {
+    name: String,
+    aliases: Vec<String>,
+    description: String,
+    args: Vec<ArgSpec>,
+    handler: Arc<dyn CommandHandler>,
+}
+
+impl Command {
+    pub fn new<H: CommandHandler + 'static>(name: &str, handler: H) -> Command {
+        Command {
+            name: name.to_string(),
+            aliases: Vec::new(),
+            description: String::new(),
+            args: Vec::new(),
+            handler: Arc::new(handler),
+        }
+    }
+
+    pub fn alias(mut self, alias: &str) -> Command {
+        self.aliases.push(alias.to_string());
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Command {
+        self.description = description.to_string();
+        self
+    }
+
+    /// 必需参数，必须在可选参数之前
+    pub fn arg(self, name: &str, kind: ArgKind) -> Command {
+        self.push_arg(name, kind, false)
+    }
+
+    /// 可选参数
+    pub fn optional_arg(self, name: &str, kind: ArgKind) -> Command {
+        self.push_arg(name, kind, true)
+    }
+
+    fn push_arg(mut self, name: &str, kind: ArgKind, optional: bool) -> Command {
+        assert!(self.args.last().map(|arg| arg.kind != ArgKind::Rest).unwrap_or(true), "Rest 参数只能是最后一个参数");
+        assert!(optional || self.args.iter().all(|arg| !arg.optional), "必需参数必须在可选参数之前");
+        self.args.push(ArgSpec { name: name.to_string(), kind, optional });
+        self
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
+
+    /// 用法，例如 /roll <sides:int> [count:int]
+    pub fn usage(&self, prefix: &str) -> String {
+        let mut usage = format!("{}{}", prefix, self.name);
+        for arg in &self.args {
+            usage.push(' ');
+            usage.push_str(&arg.to_string());
+        }
+        usage
+    }
+
+    /// 按参数定义解析命令名之后的 token
+    fn parse_args(&self, message: &[Message], tokens: &[Token]) -> Result<Args, ArgError> {
+        let mut values = Vec::new();
+        let mut rest = tokens;
+        for spec in &self.args {
+            if spec.kind == ArgKind::Rest {
+                if rest.is_empty() && !spec.optional {
+                    return Err(ArgError::Missing(spec.name.clone()));
+                }
+                if let Some(first) = rest.first() {
+                    values.push((spec.name.clone(), Arg::Str(text_from(message, first))));
+                }
+                rest = &[];
+                break;
+            }
+            let token = match rest.split_first() {
+                Some((token, remaining)) => {
+                    rest = remaining;
+                    token
+                }
+                None if spec.optional => break,
+                None => return Err(ArgError::Missing(spec.name.clone())),
+            };
+            let invalid = || ArgError::Invalid { name: spec.name.clone(), value: token.raw.clone() };
+            let value = match (spec.kind, &token.kind) {
+                (ArgKind::Int, TokenKind::Word(word)) => Arg::Int(word.parse().map_err(|_| invalid())?),
+                (ArgKind::At, TokenKind::At(qq)) => Arg::At(*qq),
+                (ArgKind::At, TokenKind::Word(word)) => Arg::At(word.parse().map_err(|_| invalid())?),
+                (ArgKind::Str, TokenKind::Word(word)) => Arg::Str(word.clone()),
+                _ => return Err(invalid()),
+            };
+            values.push((spec.name.clone(), value));
+        }
+        if !rest.is_empty() {
+            return Err(ArgError::TooMany);
+        }
+        Ok(Args { values })
+    }
+}
+
+///
+/// 命令集合，作为 EventHandler 注册，处理私聊和群消息中的命令
+///
+/// 群消息可以用 @机器人 开头。自带 help 命令，列出所有命令的用法。
+/// 参数错误时回复错误原因和用法。
+///
+#[derive(Clone)]
+pub struct Commands {
+    prefixes: Vec<String>,
+    commands: Vec<Command>,
+}
+
+impl Default for Commands {
+    fn default() -> Self {
+        Commands {
+            prefixes: vec!["/".to_string()],
+            commands: Vec::new(),
+        }
+    }
+}
+
+impl Commands {
+    /// 默认前缀为 /
+    pub fn new() -> Commands {
+        Default::default()
+    }
+
+    /// 设置命令前缀，替换默认的 /
+    pub fn prefixes(mut self, prefixes: &[&str]) -> Commands {
+        self.prefixes = prefixes.iter().map(|prefix| prefix.to_string()).collect();
+        self
+    }
+
+    pub fn command(mut self, command: Command) -> Commands {
+        self.commands.push(command);
+        self
+    }
+
+    fn prefix(&self) -> &str {
+        self.prefixes.first().map(String::as_str).unwrap_or_default()
+    }
+
+    /// 所有命令的用法和说明
+    pub fn help_text(&self) -> String {
+        let prefix = self.prefix();
+        let mut help = String::from("命令列表：");
+        for command in &self.commands {
+            help.push('\n');
+            help.push_str(&command.usage(prefix));
+            if !command.aliases.is_empty() {
+                let aliases: Vec<String> = command.aliases.iter().map(|alias| format!("{}{}", prefix, alias)).collect();
+                help.push_str(&format!("（{}）", aliases.join(" ")));
+            }
+            if !command.description.is_empty() {
+                help.push_str(" - ");
+                help.push_str(&command.description);
+            }
+        }
+        help
+    }
+
+    ///
+    /// 解析消息
+    ///
+    /// @return 不是命令时返回 None，否则返回命令和参数解析结果，或者需要回复的文本
+    ///
+    fn parse(&self, message: &[Message]) -> Option<Result<(&Command, Args), String>> {
+        let tokens = tokenize(message);
+        let (first, rest) = tokens.split_first()?;
+        let word = match &first.kind {
+            TokenKind::Word(word) if !first.quoted => word,
+            _ => return None,
+        };
+        let name = self.prefixes.iter().find_map(|prefix| word.strip_prefix(prefix.as_str()))?;
+        if name == "help" && !self.commands.iter().any(|command| command.matches("help")) {
+            let help = match rest.first() {
+                Some(Token { kind: TokenKind::Word(name), .. }) => match self.commands.iter().find(|command| command.matches(name)) {
+                    Some(command) => command.usage(self.prefix()) + if command.description.is_empty() { "" } else { "\n" } + &command.description,
+                    None => format!("没有命令 {}", name),
+                },
+                _ => self.help_text(),
+            };
+            return Some(Err(help));
+        }
+        let command = self.commands.iter().find(|command| command.matches(name))?;
+        Some(command.parse_args(message, rest)
+            .map(|args| (command, args))
+            .map_err(|err| format!("{}\n用法：{}", err, command.usage(self.prefix()))))
+    }
+
+    /// 处理命令，是命令时（包括回复用法和帮助）返回 Stop，不再交给后面的处理器
+    async fn handle(&self, mut bot: Bot, user_id: i64, group_id: Option<i64>, message_id: i32, message: &[Message]) -> Propagation {
+        let (command, args) = match self.parse(message) {
+            None => return Propagation::Continue,
+            Some(Ok(parsed)) => parsed,
+            Some(Err(reply)) => {
+                let _ = match group_id {
+                    Some(group_id) => bot.send_group_message(group_id, msg::text(&reply)).await.map(|_| ()),
+                    None => bot.send_private_message(user_id, msg::text(&reply)).await.map(|_| ()),
+                };
+                return Propagation::Stop;
+            }
+        };
+        command.handler.handle(bot, CommandCall {
+            name: command.name.clone(),
+            args,
+            user_id,
+            group_id,
+            message_id,
+        }).await;
+        Propagation::Stop
+    }
+}
+
+#[async_trait]
+impl EventHandler for Commands {
+    async fn on_private_message(&self, ctx: &EventContext<PrivateMessageEvent>) -> Propagation {
+        self.handle(ctx.bot.clone(), ctx.user_id, None, ctx.message_id, &ctx.message).await
+    }
+
+    async fn on_group_message(&self, ctx: &EventContext<GroupMessageEvent>) -> Propagation {
+        let chain = MessageChain::from(ctx.message.clone());
+        let message = chain.strip_leading_at(ctx.bot.bot_id).unwrap_or(chain);
+        self.handle(ctx.bot.clone(), ctx.user_id, Some(ctx.group_id), ctx.message_id, &message).await
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    Word(String),
+    At(i64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    kind: TokenKind,
+    /// 原始文本，用于错误提示
+    raw: String,
+    quoted: bool,
+    /// 所在消息段的下标，以及在文本中的字节偏移，用于 Rest 参数
+    segment: usize,
+    offset: usize,
+}
+
+///
+/// 把消息切分为 token：文本按空白切分，双引号括起来的部分是一个 token（支持 \" 转义），
+/// @某人 是一个 token，其他消息段忽略
+///
+fn tokenize(message: &[Message]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (index, segment) in message.iter().enumerate() {
+        match segment.r#type.as_str() {
+            "text" => tokenize_text(segment.data.get("text").map(String::as_str).unwrap_or_default(), index, &mut tokens),
+            "at" => {
+                if let Some(qq) = segment.data.get("qq").and_then(|qq| qq.parse().ok()) {
+                    tokens.push(Token { kind: TokenKind::At(qq), raw: format!("@{}", qq), quoted: false, segment: index, offset: 0 });
+                }
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+fn tokenize_text(text: &str, segment: usize, tokens: &mut Vec<Token>) {
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(offset, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut word = String::new();
+        let mut raw = String::new();
+        if c == '"' {
+            raw.push(c);
+            chars.next();
+            let mut closed = false;
+            while let Some((_, c)) = chars.next() {
+                raw.push(c);
+                match c {
+                    '\\' if matches!(chars.peek(), Some((_, '"'))) => {
+                        raw.push('"');
+                        word.push(chars.next().unwrap().1);
+                    }
+                    '"' => {
+                        closed = true;
+                        break;
+                    }
+                    _ => word.push(c),
+                }
+            }
+            if closed {
+                tokens.push(Token { kind: TokenKind::Word(word), raw, quoted: true, segment, offset });
+                continue;
+            }
+            // 没有闭合的引号按普通文本处理
+            word = raw.clone();
+        }
+        while let Some(&(_, c)) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            word.push(c);
+            raw.push(c);
+            chars.next();
+        }
+        tokens.push(Token { kind: TokenKind::Word(word), raw, quoted: false, segment, offset });
+    }
+}
+
+/// 从 token 开始到消息结尾的原始文本，保留空白和引号，@某人 显示为 @QQ 号
+fn text_from(message: &[Message], token: &Token) -> String {
+    let mut text = String::new();
+    for (index, segment) in message.iter().enumerate().skip(token.segment) {
+        match segment.r#type.as_str() {
+            "text" => {
+                let segment_text = segment.data.get("text").map(String::as_str).unwrap_or_default();
+                text.push_str(if index == token.segment { &segment_text[token.offset..] } else { segment_text });
+            }
+            "at" => {
+                if let Some(qq) = segment.data.get("qq").and_then(|qq| qq.parse::<i64>().ok()) {
+                    text.push_str(&format!("@{}", qq));
+                }
+            }
+            _ => {}
+        }
+    }
+    text.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::*;
+
+    async fn noop(_bot: Bot, _call: CommandCall) {}
+
+    fn commands() -> Commands {
+        Commands::new()
+            .prefixes(&["/", "!"])
+            .command(Command::new("roll", noop)
+                .alias("r")
+                .description("掷骰子")
+                .arg("sides", ArgKind::Int)
+                .optional_arg("count", ArgKind::Int))
+            .command(Command::new("kick", noop)
+                .arg("target", ArgKind::At)
+                .optional_arg("reason", ArgKind::Rest))
+            .command(Command::new("say", noop)
+                .arg("who", ArgKind::Str)
+                .arg("words", ArgKind::Rest))
+    }
+
+    fn parse(commands: &Commands, message: Vec<Message>) -> Option<Result<(String, Args), String>> {
+        commands.parse(&message).map(|result| result.map(|(command, args)| (command.name.clone(), args)))
+    }
+
+    #[test]
+    fn tokenize_quotes() {
+        let tokens = tokenize(&(text(r#"say "hello \"world\"" x "#) + at(10001) + face(1) + text(r#" "open"#)));
+        let words: Vec<&TokenKind> = tokens.iter().map(|token| &token.kind).collect();
+        assert_eq!(words, vec![
+            &TokenKind::Word("say".to_string()),
+            &TokenKind::Word(r#"hello "world""#.to_string()),
+            &TokenKind::Word("x".to_string()),
+            &TokenKind::At(10001),
+            &TokenKind::Word(r#""open"#.to_string()),
+        ]);
+    }
+
+    #[test]
+    fn parse_typed_args() {
+        let commands = commands();
+        let (name, args) = parse(&commands, text("!r 6 2").into()).unwrap().unwrap();
+        assert_eq!(name, "roll");
+        assert_eq!(args.int("sides"), Some(6));
+        assert_eq!(args.int("count"), Some(2));
+
+        let (_, args) = parse(&commands, text("/roll 20").into()).unwrap().unwrap();
+        assert_eq!(args.int("count"), None);
+
+        let (_, args) = parse(&commands, text("/kick ") + at(10001) + text(" spam  and flood")).unwrap().unwrap();
+        assert_eq!(args.at("target"), Some(10001));
+        assert_eq!(args.str("reason"), Some("spam  and flood"));
+
+        let (_, args) = parse(&commands, text("/kick 10001").into()).unwrap().unwrap();
+        assert_eq!(args.at("target"), Some(10001));
+
+        let (_, args) = parse(&commands, text(r#"/say "Alice B" hi there"#).into()).unwrap().unwrap();
+        assert_eq!(args.str("who"), Some("Alice B"));
+        assert_eq!(args.str("words"), Some("hi there"));
+    }
+
+    #[test]
+    fn rest_keeps_original_text() {
+        let commands = commands();
+        let (_, args) = parse(&commands, text(r#"/say bob   "hello  world"   and  "#) + at(10001) + text(" \"bye\"  ")).unwrap().unwrap();
+        assert_eq!(args.str("who"), Some("bob"));
+        assert_eq!(args.str("words"), Some(r#""hello  world"   and  @10001 "bye""#));
+
+        let (_, args) = parse(&commands, text("/kick ") + at(10001) + text("\tspam,\n  flood")).unwrap().unwrap();
+        assert_eq!(args.str("reason"), Some("spam,\n  flood"));
+    }
+
+    #[test]
+    fn not_a_command() {
+        let commands = commands();
+        assert!(parse(&commands, text("roll 6").into()).is_none());
+        assert!(parse(&commands, text("/unknown 6").into()).is_none());
+        assert!(parse(&commands, text(r#""/roll" 6"#).into()).is_none());
+        assert!(parse(&commands, image("http://a/b.png").into()).is_none());
+    }
+
+    #[test]
+    fn usage_errors() {
+        let commands = commands();
+        assert_eq!(parse(&commands, text("/roll").into()), Some(Err("缺少参数 sides\n用法：/roll <sides:int> [count:int]".to_string())));
+        assert_eq!(parse(&commands, text("/roll six").into()), Some(Err("参数 sides 的值 six 不正确\n用法：/roll <sides:int> [count:int]".to_string())));
+        assert_eq!(parse(&commands, text("/roll 6 2 1").into()), Some(Err("参数过多\n用法：/roll <sides:int> [count:int]".to_string())));
+        assert_eq!(parse(&commands, text("/kick bob").into()), Some(Err("参数 target 的值 bob 不正确\n用法：/kick <target:@> [reason...]".to_string())));
+    }
+
+    #[test]
+    fn help() {
+        let commands = commands();
+        let help = "命令列表：\n/roll <sides:int> [count:int]（/r） - 掷骰子\n/kick <target:@> [reason...]\n/say <who> <words...>";
+        assert_eq!(commands.help_text(), help);
+        assert_eq!(parse(&commands, text("/help").into()), Some(Err(help.to_string())));
+        assert_eq!(parse(&commands, text("/help r").into()), Some(Err("/roll <sides:int> [count:int]\n掷骰子".to_string())));
+    }
+
+    #[tokio::test]
+    async fn commands_stop_propagation() {
+        let (api_sender, _api_receiver) = tokio::sync::mpsc::channel(10);
+        let bot = Bot::new(10001, api_sender).with_timeout(std::time::Duration::from_millis(10));
+        let commands = commands();
+        let propagation = |message: Vec<Message>| {
+            let ctx = EventContext {
+                bot: bot.clone(),
+                event: PrivateMessageEvent { user_id: 1, message, ..Default::default() },
+            };
+            let commands = &commands;
+            async move { commands.on_private_message(&ctx).await }
+        };
+
+        assert_eq!(propagation(text("/roll 6").into()).await, Propagation::Stop);
+        // 回复用法和帮助
+        assert_eq!(propagation(text("/roll x").into()).await, Propagation::Stop);
+        assert_eq!(propagation(text("/help").into()).await, Propagation::Stop);
+        assert_eq!(propagation(text("roll 6").into()).await, Propagation::Continue);
+    }
+}
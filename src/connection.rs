@@ -0,0 +1,242 @@
+use crate::bot::Bot;
+use crate::codec::{Codec, ConnectionCodec};
+use crate::dispatcher::{is_event, Dispatcher};
+use crate::middleware::Middlewares;
+use crate::registry::BotRegistry;
+use crate::shutdown::{self, InFlight, ShutdownReceiver};
+use axum::extract::ws::{CloseFrame, Message};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use std::fmt::Display;
+use tokio::sync::{mpsc, oneshot};
+use tracing::Instrument;
+
+/// 默认连续解码失败多少次后断开连接
+pub const DEFAULT_MAX_DECODE_ERRORS: u32 = 10;
+
+/// 日志中最多显示多少字节的原始数据
+const MAX_HEX_DUMP: usize = 256;
+
+/// 服务器和客户端的所有连接共享的配置
+#[derive(Clone)]
+pub(crate) struct ConnectionConfig {
+    pub(crate) dispatcher: Dispatcher,
+    pub(crate) registry: BotRegistry,
+    pub(crate) middlewares: Middlewares,
+    pub(crate) shutdown: ShutdownReceiver,
+    pub(crate) max_decode_errors: u32,
+}
+
+///
+/// 处理一个 websocket 连接，直到连接断开或退出
+///
+/// 反向连接和正向连接都使用这里的事件循环，stream 的消息需要先转换为 axum 的 Message
+///
+/// @param stream websocket 连接
+/// @param bot_id 机器人 QQ 号
+/// @param config 共享的配置
+/// @param codec  固定的编码，None 表示由收到的第一个 Frame 决定
+///
+pub(crate) async fn run<S, E>(stream: S, bot_id: i64, config: ConnectionConfig, codec: Option<Codec>)
+where
+    S: Stream<Item = Result<Message, E>> + Sink<Message, Error = E> + Send + 'static,
+    E: Display + Send + 'static,
+{
+    let ConnectionConfig { dispatcher, registry, middlewares, mut shutdown, max_decode_errors } = config;
+    tracing::info!("bot connected");
+    let (mut ws_out, mut ws_in) = stream.split();
+    let (api_sender, mut api_receiver) = mpsc::channel(10); // api channel
+    let (close_sender, mut close_receiver) = oneshot::channel::<CloseFrame<'static>>();
+    let bot = Bot::new(bot_id, api_sender).with_middlewares(middlewares);
+    registry.connect(bot.clone());
+    // 正在执行的事件处理器
+    let handlers = InFlight::new();
+    let codec = ConnectionCodec::new(codec);
+
+    // 发送 api req，退出时发送 Close 帧
+    let mut send_task = tokio::spawn({
+        let mut codec = codec.clone();
+        async move {
+            // 编码由第一个 Frame 决定时，确定之前 API 请求留在 channel 中等待
+            let codec = tokio::select! {
+                codec = codec.known() => codec,
+                close_frame = &mut close_receiver => {
+                    let _ = ws_out.send(Message::Close(close_frame.ok())).await;
+                    return;
+                }
+            };
+            loop {
+                let ws_message = tokio::select! {
+                    frame = api_receiver.recv() => match frame {
+                        Some(frame) => match codec.encode(&frame) {
+                            Ok(ws_message) => ws_message,
+                            Err(err) => {
+                                tracing::error!(echo = %frame.echo, error = %err, "failed to encode frame");
+                                continue;
+                            }
+                        },
+                        None => break,
+                    },
+                    close_frame = &mut close_receiver => Message::Close(close_frame.ok()),
+                };
+                let closing = matches!(ws_message, Message::Close(_));
+                if let Err(err) = ws_out.send(ws_message).await {
+                    tracing::warn!(error = %err, "failed to send websocket message");
+                    break;
+                }
+                if closing {
+                    break;
+                }
+            }
+        }.in_current_span()
+    });
+
+    tokio::spawn({
+        let bot = bot.clone();
+        let dispatcher = dispatcher.clone();
+        let handler = handlers.enter();
+        async move {
+            dispatcher.dispatch_connected(bot).await;
+            drop(handler);
+        }.in_current_span()
+    });
+
+    // 接受 event 和 api resp，需要主动断开时返回 Close 帧
+    let mut recv_task = tokio::spawn({
+        let bot = bot.clone();
+        let dispatcher = dispatcher.clone();
+        let handlers = handlers.clone();
+        let shutdown = shutdown.clone();
+        async move {
+            // 连续解码失败次数和总次数
+            let mut decode_errors = 0;
+            let mut total_decode_errors = 0u64;
+            while let Some(ws_message) = ws_in.next().await {
+                let ws_message = match ws_message {
+                    Ok(ws_message) => ws_message,
+                    Err(err) => {
+                        tracing::warn!(error = %err, "failed to receive websocket message");
+                        break;
+                    }
+                };
+                let (received, buf) = match ws_message {
+                    Message::Binary(buf) => (Codec::Protobuf, buf),
+                    Message::Text(text) => (Codec::Json, text.into_bytes()),
+                    Message::Close(_) => break,
+                    _ => continue,
+                };
+                if codec.detect(received) != received {
+                    tracing::warn!(?received, expected = ?codec.detect(received), len = buf.len(), "ignored message with wrong codec");
+                    continue;
+                }
+                let frame = match received.decode(&buf) {
+                    Ok(frame) => {
+                        decode_errors = 0;
+                        frame
+                    }
+                    Err(err) => {
+                        decode_errors += 1;
+                        total_decode_errors += 1;
+                        tracing::warn!(
+                            error = %err,
+                            len = buf.len(),
+                            hex = %hex_dump(&buf),
+                            consecutive = decode_errors,
+                            total = total_decode_errors,
+                            "failed to decode frame",
+                        );
+                        let bot = bot.clone();
+                        let dispatcher = dispatcher.clone();
+                        let handler = handlers.enter();
+                        tokio::spawn(async move {
+                            dispatcher.dispatch_decode_error(bot, &err, &buf).await;
+                            drop(handler);
+                        }.in_current_span());
+                        if max_decode_errors > 0 && decode_errors >= max_decode_errors {
+                            tracing::error!(consecutive = decode_errors, "too many decode errors, closing connection");
+                            return Some(CloseFrame { code: 1007, reason: "too many undecodable frames".into() });
+                        }
+                        continue;
+                    }
+                };
+                if frame.data.as_ref().is_some_and(is_event) {
+                    // 正在退出，不再处理新的事件
+                    if shutdown.borrow().is_some() {
+                        continue;
+                    }
+                    let bot = bot.clone();
+                    let dispatcher = dispatcher.clone();
+                    let handler = handlers.enter();
+                    tokio::spawn(async move {
+                        dispatcher.dispatch_frame(bot, frame).await;
+                        drop(handler);
+                    }.in_current_span());
+                } else if frame.echo.is_empty() {
+                    // 不是 event 也没有 echo，无法处理
+                    tracing::warn!(frame_type = frame.frame_type, "unknown frame");
+                } else {
+                    // 不是 event，一定是 api resp
+                    let echo = frame.echo.clone();
+                    if !bot.handle_response(frame) {
+                        tracing::warn!(%echo, "dropped response, no pending request");
+                    }
+                }
+            }
+            None
+        }.in_current_span()
+    });
+
+    tokio::select! {
+        _ = (&mut send_task) => {}
+        close_frame = (&mut recv_task) => {
+            if let Ok(Some(close_frame)) = close_frame {
+                let _ = close_sender.send(close_frame);
+                let _ = tokio::time::timeout(shutdown::CLOSE_TIMEOUT, &mut send_task).await;
+            }
+        }
+        deadline = shutdown::deadline(&mut shutdown) => {
+            tracing::info!("shutting down, draining handlers");
+            // 等待正在执行的处理器和 API 调用，最多等到截止时间
+            let _ = tokio::time::timeout_at(deadline, drain(&handlers, &bot)).await;
+            let _ = close_sender.send(CloseFrame { code: 1001, reason: "shutting down".into() });
+            // 等待对端回复 Close
+            let _ = tokio::time::timeout(shutdown::CLOSE_TIMEOUT, &mut recv_task).await;
+        }
+    }
+    send_task.abort();
+    recv_task.abort();
+    // 连接断开，未完成的请求立即返回 ChannelClosed
+    if let Ok(mut resp_promises) = bot.resp_promises.lock() {
+        resp_promises.clear();
+    }
+    bot.sessions.clear();
+    registry.disconnect(&bot);
+    tracing::info!("bot disconnected");
+    dispatcher.dispatch_disconnected(bot).await;
+}
+
+/// 十六进制显示，最多显示 MAX_HEX_DUMP 字节
+fn hex_dump(buf: &[u8]) -> String {
+    let mut hex: Vec<String> = buf.iter().take(MAX_HEX_DUMP).map(|byte| format!("{:02x}", byte)).collect();
+    if buf.len() > MAX_HEX_DUMP {
+        hex.push(format!("... ({} bytes)", buf.len()));
+    }
+    hex.join(" ")
+}
+
+/// 等待事件处理器和其他 task 发起的 API 调用完成
+async fn drain(handlers: &InFlight, bot: &Bot) {
+    handlers.wait().await;
+    bot.calls.wait().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_dump_is_truncated() {
+        assert_eq!(hex_dump(&[0x0a, 0xff]), "0a ff");
+        let dump = hex_dump(&[0; MAX_HEX_DUMP + 1]);
+        assert!(dump.ends_with(&format!("00 ... ({} bytes)", MAX_HEX_DUMP + 1)));
+    }
+}
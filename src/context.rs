@@ -0,0 +1,260 @@
+use crate::bot::Bot;
+use crate::error::BotError;
+use crate::msg;
+use crate::onebot::*;
+use crate::session::MessageEvent;
+use std::ops::Deref;
+use std::time::Duration;
+
+///
+/// 事件的公共信息，EventContext 根据这些信息选择 API
+///
+pub trait Event: Clone + Send + Sync + 'static {
+    /// 触发事件的用户
+    fn user_id(&self) -> i64;
+
+    /// 事件所在的群，私聊、好友相关事件为 None
+    fn group_id(&self) -> Option<i64> {
+        None
+    }
+
+    /// 事件对应的消息，只有消息事件有
+    fn message_id(&self) -> Option<i32> {
+        None
+    }
+}
+
+macro_rules! impl_event {
+    ($event:ty) => {
+        impl Event for $event {
+            fn user_id(&self) -> i64 {
+                self.user_id
+            }
+        }
+    };
+    ($event:ty, group) => {
+        impl Event for $event {
+            fn user_id(&self) -> i64 {
+                self.user_id
+            }
+
+            fn group_id(&self) -> Option<i64> {
+                Some(self.group_id)
+            }
+        }
+    };
+}
+
+impl Event for PrivateMessageEvent {
+    fn user_id(&self) -> i64 {
+        self.user_id
+    }
+
+    fn message_id(&self) -> Option<i32> {
+        Some(self.message_id)
+    }
+}
+
+impl Event for GroupMessageEvent {
+    fn user_id(&self) -> i64 {
+        self.user_id
+    }
+
+    fn group_id(&self) -> Option<i64> {
+        Some(self.group_id)
+    }
+
+    fn message_id(&self) -> Option<i32> {
+        Some(self.message_id)
+    }
+}
+
+impl Event for MessageEvent {
+    fn user_id(&self) -> i64 {
+        MessageEvent::user_id(self)
+    }
+
+    fn group_id(&self) -> Option<i64> {
+        MessageEvent::group_id(self)
+    }
+
+    fn message_id(&self) -> Option<i32> {
+        Some(MessageEvent::message_id(self))
+    }
+}
+
+impl_event!(GroupUploadNoticeEvent, group);
+impl_event!(GroupAdminNoticeEvent, group);
+impl_event!(GroupDecreaseNoticeEvent, group);
+impl_event!(GroupIncreaseNoticeEvent, group);
+impl_event!(GroupBanNoticeEvent, group);
+impl_event!(FriendAddNoticeEvent);
+// 撤回通知的 message_id 是已经撤回的消息，不能再回复或撤回
+impl_event!(GroupRecallNoticeEvent, group);
+impl_event!(FriendRecallNoticeEvent);
+impl_event!(FriendRequestEvent);
+impl_event!(GroupRequestEvent, group);
+
+///
+/// 事件上下文，包含事件和收到事件的 Bot
+///
+/// 回复、撤回、踢人、禁言时根据事件类型选择私聊或群 API
+///
+#[derive(Clone)]
+pub struct EventContext<E> {
+    pub bot: Bot,
+    pub event: E,
+}
+
+impl<E> Deref for EventContext<E> {
+    type Target = E;
+
+    fn deref(&self) -> &E {
+        &self.event
+    }
+}
+
+impl<E: Event> EventContext<E> {
+    pub fn new(bot: Bot, event: E) -> EventContext<E> {
+        EventContext { bot, event }
+    }
+
+    fn require_group(&self) -> Result<i64, BotError> {
+        self.event.group_id().ok_or(BotError::Unsupported("event is not from a group"))
+    }
+
+    fn require_message(&self) -> Result<i32, BotError> {
+        self.event.message_id().ok_or(BotError::Unsupported("event has no message"))
+    }
+
+    ///
+    /// 回复消息，群事件发到群里，其他事件私聊发给用户
+    ///
+    /// @param message 消息
+    /// @return 发出的消息的 message_id
+    ///
+    pub async fn reply<T: Into<Vec<Message>>>(&self, message: T) -> Result<i32, BotError> {
+        let mut bot = self.bot.clone();
+        match self.event.group_id() {
+            Some(group_id) => bot.send_group_message(group_id, message).await.map(|resp| resp.message_id),
+            None => bot.send_private_message(self.event.user_id(), message).await.map(|resp| resp.message_id),
+        }
+    }
+
+    ///
+    /// 引用事件的消息回复，只能用于消息事件
+    ///
+    /// @param message 消息
+    /// @return 发出的消息的 message_id
+    ///
+    pub async fn reply_quoted<T: Into<Vec<Message>>>(&self, message: T) -> Result<i32, BotError> {
+        let message_id = self.require_message()?;
+        self.reply(msg::reply(message_id) + message.into()).await
+    }
+
+    /// 撤回事件的消息，只能用于消息事件
+    pub async fn recall(&self) -> Result<DeleteMsgResp, BotError> {
+        let message_id = self.require_message()?;
+        self.bot.clone().delete_msg(message_id).await
+    }
+
+    /// 把用户踢出群，只能用于群事件
+    pub async fn kick_sender(&self) -> Result<SetGroupKickResp, BotError> {
+        let group_id = self.require_group()?;
+        self.bot.clone().set_group_kick(group_id, self.event.user_id(), false).await
+    }
+
+    ///
+    /// 禁言用户，只能用于群事件
+    ///
+    /// @param duration 禁言时长，精确到秒，0 表示解除禁言
+    ///
+    pub async fn ban_sender(&self, duration: Duration) -> Result<SetGroupBanResp, BotError> {
+        let group_id = self.require_group()?;
+        let duration = duration.as_secs().min(i32::MAX as u64) as i32;
+        self.bot.clone().set_group_ban(group_id, self.event.user_id(), duration).await
+    }
+
+    ///
+    /// 等待用户在同一个群（或私聊）的下一条消息
+    ///
+    /// @param timeout 超时时间
+    ///
+    pub async fn wait_next(&self, timeout: Duration) -> Result<MessageEvent, BotError> {
+        self.bot.wait_next(self.event.user_id(), self.event.group_id(), timeout).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::onebot::frame::Data;
+    use crate::msg::text;
+    use tokio::sync::mpsc;
+
+    ///
+    /// 启动一个假的对端，记录收到的请求并返回对应的响应
+    ///
+    fn fake_bot() -> (Bot, mpsc::Receiver<Data>) {
+        let (api_sender, mut api_receiver) = mpsc::channel::<Frame>(10);
+        let (data_sender, data_receiver) = mpsc::channel(10);
+        let bot = Bot::new(10001, api_sender);
+        let responder = bot.clone();
+        tokio::spawn(async move {
+            while let Some(frame) = api_receiver.recv().await {
+                let data = frame.data.clone().unwrap();
+                let resp = match &data {
+                    Data::SendPrivateMsgReq(_) => Data::SendPrivateMsgResp(SendPrivateMsgResp { message_id: 1 }),
+                    Data::SendGroupMsgReq(_) => Data::SendGroupMsgResp(SendGroupMsgResp { message_id: 2 }),
+                    Data::DeleteMsgReq(_) => Data::DeleteMsgResp(DeleteMsgResp {}),
+                    Data::SetGroupKickReq(_) => Data::SetGroupKickResp(SetGroupKickResp {}),
+                    Data::SetGroupBanReq(_) => Data::SetGroupBanResp(SetGroupBanResp {}),
+                    _ => continue,
+                };
+                let _ = data_sender.send(data).await;
+                responder.handle_response(Frame { echo: frame.echo, ok: true, data: Some(resp), ..Default::default() });
+            }
+        });
+        (bot, data_receiver)
+    }
+
+    #[tokio::test]
+    async fn helpers_pick_api_by_event_type() {
+        let (bot, mut requests) = fake_bot();
+
+        let private = EventContext::new(bot.clone(), PrivateMessageEvent { user_id: 1, message_id: 7, ..Default::default() });
+        assert_eq!(private.reply(text("hi")).await.unwrap(), 1);
+        assert_eq!(requests.recv().await.unwrap(), Data::SendPrivateMsgReq(SendPrivateMsgReq {
+            user_id: 1,
+            message: vec![text("hi")],
+            ..Default::default()
+        }));
+        assert!(matches!(private.kick_sender().await, Err(BotError::Unsupported(_))));
+
+        let group = EventContext::new(bot.clone(), GroupMessageEvent { group_id: 100, user_id: 1, message_id: 8, ..Default::default() });
+        assert_eq!(group.reply_quoted(text("hi")).await.unwrap(), 2);
+        assert_eq!(requests.recv().await.unwrap(), Data::SendGroupMsgReq(SendGroupMsgReq {
+            group_id: 100,
+            message: vec![msg::reply(8), text("hi")],
+            ..Default::default()
+        }));
+        group.recall().await.unwrap();
+        assert_eq!(requests.recv().await.unwrap(), Data::DeleteMsgReq(DeleteMsgReq { message_id: 8 }));
+        group.kick_sender().await.unwrap();
+        assert_eq!(requests.recv().await.unwrap(), Data::SetGroupKickReq(SetGroupKickReq {
+            group_id: 100,
+            user_id: 1,
+            reject_add_request: false,
+        }));
+        group.ban_sender(Duration::from_secs(60)).await.unwrap();
+        assert_eq!(requests.recv().await.unwrap(), Data::SetGroupBanReq(SetGroupBanReq {
+            group_id: 100,
+            user_id: 1,
+            duration: 60,
+        }));
+
+        let notice = EventContext::new(bot, GroupIncreaseNoticeEvent { group_id: 100, user_id: 3, ..Default::default() });
+        assert!(matches!(notice.recall().await, Err(BotError::Unsupported(_))));
+        assert_eq!(notice.reply(text("welcome")).await.unwrap(), 2);
+    }
+}
@@ -0,0 +1,270 @@
+use crate::bot::Bot;
+use crate::context::EventContext;
+use crate::error::BotError;
+use crate::handler::{EventHandler, Propagation};
+use crate::middleware::EventNext;
+use crate::onebot::frame::Data;
+use crate::onebot::Frame;
+use std::sync::Arc;
+
+///
+/// 事件分发器，把收到的事件按优先级从高到低交给每个 EventHandler，优先级相同时按注册顺序
+///
+/// 处理器返回 Propagation::Stop 时，后面的处理器不再收到该事件
+///
+#[derive(Clone, Default)]
+pub struct Dispatcher {
+    /// 按优先级从高到低排序
+    handlers: Vec<(i32, Arc<dyn EventHandler>)>,
+}
+
+impl Dispatcher {
+    pub fn new() -> Dispatcher {
+        Default::default()
+    }
+
+    ///
+    /// 注册事件处理器
+    ///
+    /// @param handler 事件处理器
+    /// @return Dispatcher 本身，便于链式调用
+    ///
+    pub fn add_handler<H: EventHandler + 'static>(self, handler: H) -> Dispatcher {
+        let priority = handler.priority();
+        self.add_handler_with_priority(handler, priority)
+    }
+
+    ///
+    /// 使用指定优先级注册事件处理器，忽略 EventHandler::priority
+    ///
+    /// @param handler  事件处理器
+    /// @param priority 优先级，越大越先执行
+    /// @return Dispatcher 本身，便于链式调用
+    ///
+    pub fn add_handler_with_priority<H: EventHandler + 'static>(mut self, handler: H, priority: i32) -> Dispatcher {
+        // 插入到同优先级的最后，保持注册顺序
+        let index = self.handlers.iter().position(|(p, _)| *p < priority).unwrap_or(self.handlers.len());
+        self.handlers.insert(index, (priority, Arc::new(handler)));
+        self
+    }
+
+    /// 通知所有事件处理器 Bot 已连接
+    pub async fn dispatch_connected(&self, bot: Bot) {
+        for (_, handler) in &self.handlers {
+            handler.on_bot_connected(bot.clone()).await;
+        }
+    }
+
+    /// 通知所有事件处理器 Bot 已断开
+    pub async fn dispatch_disconnected(&self, bot: Bot) {
+        for (_, handler) in &self.handlers {
+            handler.on_bot_disconnected(bot.clone()).await;
+        }
+    }
+
+    /// 通知所有事件处理器收到无法解码的 Frame
+    pub async fn dispatch_decode_error(&self, bot: Bot, error: &BotError, raw: &[u8]) {
+        for (_, handler) in &self.handlers {
+            handler.on_decode_error(bot.clone(), error, raw).await;
+        }
+    }
+
+    ///
+    /// 事件先经过 bot 的中间件，再分发给 EventHandler
+    ///
+    /// @param bot   收到事件的 Bot
+    /// @param frame 包含事件的 Frame
+    ///
+    pub async fn dispatch_frame(&self, bot: Bot, frame: Frame) {
+        let next = EventNext { bot: &bot, middlewares: &bot.middlewares, dispatcher: self };
+        next.run(frame).await
+    }
+
+    ///
+    /// 分发事件，不经过中间件，不是事件的 Data 会被忽略，被会话接收的消息不再分发
+    ///
+    /// @param bot  收到事件的 Bot
+    /// @param data 事件
+    ///
+    pub async fn dispatch(&self, bot: Bot, data: &Data) {
+        // 先交给等待下一条消息的会话
+        if bot.sessions.offer(data) {
+            return;
+        }
+        // 每个事件只构造一次 EventContext，所有 handler 共用
+        macro_rules! dispatch {
+            ($event:expr, $method:ident) => {{
+                let ctx = EventContext::new(bot, $event.clone());
+                for (_, handler) in &self.handlers {
+                    if handler.$method(&ctx).await == Propagation::Stop {
+                        break;
+                    }
+                }
+            }};
+        }
+        match data {
+            Data::PrivateMessageEvent(event) => dispatch!(event, on_private_message),
+            Data::GroupMessageEvent(event) => dispatch!(event, on_group_message),
+            Data::GroupUploadNoticeEvent(event) => dispatch!(event, on_group_upload_notice),
+            Data::GroupAdminNoticeEvent(event) => dispatch!(event, on_group_admin_notice),
+            Data::GroupDecreaseNoticeEvent(event) => dispatch!(event, on_group_decrease_notice),
+            Data::GroupIncreaseNoticeEvent(event) => dispatch!(event, on_group_increase_notice),
+            Data::GroupBanNoticeEvent(event) => dispatch!(event, on_group_ban_notice),
+            Data::FriendAddNoticeEvent(event) => dispatch!(event, on_friend_add_notice),
+            Data::GroupRecallNoticeEvent(event) => dispatch!(event, on_group_recall_notice),
+            Data::FriendRecallNoticeEvent(event) => dispatch!(event, on_friend_recall_notice),
+            Data::FriendRequestEvent(event) => dispatch!(event, on_friend_request),
+            Data::GroupRequestEvent(event) => dispatch!(event, on_group_request),
+            _ => {}
+        }
+    }
+}
+
+///
+/// 判断 Data 是否为事件，不是事件的一定是 api resp
+///
+pub fn is_event(data: &Data) -> bool {
+    matches!(data,
+        Data::PrivateMessageEvent(_)
+        | Data::GroupMessageEvent(_)
+        | Data::GroupUploadNoticeEvent(_)
+        | Data::GroupAdminNoticeEvent(_)
+        | Data::GroupDecreaseNoticeEvent(_)
+        | Data::GroupIncreaseNoticeEvent(_)
+        | Data::GroupBanNoticeEvent(_)
+        | Data::FriendAddNoticeEvent(_)
+        | Data::GroupRecallNoticeEvent(_)
+        | Data::FriendRecallNoticeEvent(_)
+        | Data::FriendRequestEvent(_)
+        | Data::GroupRequestEvent(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::onebot::*;
+    use crate::router::Router;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+    use tokio::sync::mpsc;
+
+    type Log = Arc<Mutex<Vec<&'static str>>>;
+
+    struct Named {
+        name: &'static str,
+        priority: i32,
+        propagation: Propagation,
+        log: Log,
+    }
+
+    #[async_trait]
+    impl EventHandler for Named {
+        fn priority(&self) -> i32 {
+            self.priority
+        }
+
+        async fn on_group_message(&self, _ctx: &EventContext<GroupMessageEvent>) -> Propagation {
+            self.log.lock().unwrap().push(self.name);
+            self.propagation
+        }
+    }
+
+    fn named(name: &'static str, priority: i32, propagation: Propagation, log: &Log) -> Named {
+        Named { name, priority, propagation, log: log.clone() }
+    }
+
+    async fn dispatch_group_message(dispatcher: &Dispatcher) {
+        let (api_sender, _api_receiver) = mpsc::channel(10);
+        let bot = Bot::new(10001, api_sender);
+        dispatcher.dispatch(bot, &Data::GroupMessageEvent(GroupMessageEvent { group_id: 100, user_id: 1, ..Default::default() })).await;
+    }
+
+    /// 只处理私聊和群消息，记录收到的消息
+    struct Messages(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl EventHandler for Messages {
+        async fn on_private_message(&self, ctx: &EventContext<PrivateMessageEvent>) -> Propagation {
+            self.0.lock().unwrap().push(format!("private {}", ctx.user_id));
+            Propagation::Continue
+        }
+
+        async fn on_group_message(&self, ctx: &EventContext<GroupMessageEvent>) -> Propagation {
+            self.0.lock().unwrap().push(format!("group {} {}", ctx.group_id, ctx.user_id));
+            Propagation::Continue
+        }
+    }
+
+    fn event_frame(data: Data) -> Frame {
+        Frame { bot_id: 10001, data: Some(data), ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn message_frames_reach_handler() {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let dispatcher = Dispatcher::new().add_handler(Messages(messages.clone()));
+        let (api_sender, _api_receiver) = mpsc::channel(10);
+        let bot = Bot::new(10001, api_sender);
+
+        dispatcher.dispatch_frame(bot.clone(), event_frame(Data::PrivateMessageEvent(PrivateMessageEvent { user_id: 1, ..Default::default() }))).await;
+        dispatcher.dispatch_frame(bot.clone(), event_frame(Data::GroupMessageEvent(GroupMessageEvent { group_id: 100, user_id: 2, ..Default::default() }))).await;
+        assert_eq!(*messages.lock().unwrap(), vec!["private 1", "group 100 2"]);
+    }
+
+    #[tokio::test]
+    async fn other_frames_are_not_delivered() {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let dispatcher = Dispatcher::new().add_handler(Messages(messages.clone()));
+        let (api_sender, _api_receiver) = mpsc::channel(10);
+        let bot = Bot::new(10001, api_sender);
+
+        // 其他事件、api resp 和没有 data 的 Frame 都不会交给消息处理方法
+        dispatcher.dispatch_frame(bot.clone(), event_frame(Data::GroupRecallNoticeEvent(GroupRecallNoticeEvent { user_id: 3, ..Default::default() }))).await;
+        dispatcher.dispatch_frame(bot.clone(), event_frame(Data::FriendRequestEvent(FriendRequestEvent { user_id: 4, ..Default::default() }))).await;
+        dispatcher.dispatch_frame(bot.clone(), event_frame(Data::GetLoginInfoResp(GetLoginInfoResp { user_id: 5, ..Default::default() }))).await;
+        dispatcher.dispatch_frame(bot.clone(), Frame::default()).await;
+        assert!(messages.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn handlers_run_by_priority_then_registration_order() {
+        let log = Log::default();
+        let dispatcher = Dispatcher::new()
+            .add_handler(named("fun", 0, Propagation::Continue, &log))
+            .add_handler(named("log", 0, Propagation::Continue, &log))
+            .add_handler(named("moderation", 10, Propagation::Continue, &log))
+            .add_handler_with_priority(named("fallback", 10, Propagation::Continue, &log), -1);
+        dispatch_group_message(&dispatcher).await;
+        assert_eq!(*log.lock().unwrap(), vec!["moderation", "fun", "log", "fallback"]);
+    }
+
+    #[tokio::test]
+    async fn stop_skips_lower_priority_handlers() {
+        let log = Log::default();
+        let dispatcher = Dispatcher::new()
+            .add_handler(named("fun", 0, Propagation::Continue, &log))
+            .add_handler(named("moderation", 10, Propagation::Stop, &log))
+            .add_handler(named("audit", 20, Propagation::Continue, &log));
+        dispatch_group_message(&dispatcher).await;
+        assert_eq!(*log.lock().unwrap(), vec!["audit", "moderation"]);
+    }
+
+    #[tokio::test]
+    async fn route_stop_skips_later_routes_and_handlers() {
+        let log = Log::default();
+        let router = Router::new();
+        for (name, priority, propagation) in [("route low", 0, Propagation::Continue), ("route high", 5, Propagation::Stop)] {
+            let log = log.clone();
+            router.on_group_message().priority(priority).handle(move |_, _| {
+                log.lock().unwrap().push(name);
+                async move { propagation }
+            });
+        }
+        let dispatcher = Dispatcher::new()
+            .add_handler(named("fun", 0, Propagation::Continue, &log))
+            .add_handler_with_priority(router, 1);
+        dispatch_group_message(&dispatcher).await;
+        assert_eq!(*log.lock().unwrap(), vec!["route high"]);
+    }
+}
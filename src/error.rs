@@ -0,0 +1,73 @@
+use crate::onebot::frame::Data;
+use crate::segment::SegmentError;
+use std::collections::HashMap;
+use std::fmt;
+
+/// 调用 Bot API 时可能出现的错误
+#[derive(Debug)]
+pub enum BotError {
+    /// 连接已断开，请求无法发送或响应无法到达
+    ChannelClosed,
+    /// 等待响应超时
+    Timeout,
+    /// 等待被主动取消
+    Cancelled,
+    /// Frame 解码失败
+    Decode(prost::DecodeError),
+    /// Frame 编码失败
+    Encode(prost::EncodeError),
+    /// JSON 格式的 Frame 编码或解码失败
+    Json(serde_json::Error),
+    /// 响应中的 Data 与请求不匹配，None 表示响应没有 Data
+    UnexpectedData(Option<Box<Data>>),
+    /// 对端返回 ok: false，附带 Frame 的 extra 信息
+    Remote(HashMap<String, String>),
+    /// 严格模式下，请求中的消息段不符合规范，请求没有发送
+    InvalidSegment(Vec<SegmentError>),
+    /// 事件类型不支持该操作，比如对私聊消息禁言
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BotError::ChannelClosed => write!(f, "bot connection closed"),
+            BotError::Timeout => write!(f, "timed out"),
+            BotError::Cancelled => write!(f, "cancelled"),
+            BotError::Decode(err) => write!(f, "failed to decode frame: {}", err),
+            BotError::Encode(err) => write!(f, "failed to encode frame: {}", err),
+            BotError::Json(err) => write!(f, "invalid json frame: {}", err),
+            BotError::UnexpectedData(Some(data)) => write!(f, "unexpected response data: {:?}", data),
+            BotError::UnexpectedData(None) => write!(f, "response frame has no data"),
+            BotError::Remote(extra) => write!(f, "remote api call failed: {:?}", extra),
+            BotError::Unsupported(reason) => write!(f, "unsupported for this event: {}", reason),
+            BotError::InvalidSegment(errors) => {
+                let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+                write!(f, "invalid message segment: {}", errors.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for BotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BotError::Decode(err) => Some(err),
+            BotError::Encode(err) => Some(err),
+            BotError::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<prost::DecodeError> for BotError {
+    fn from(err: prost::DecodeError) -> Self {
+        BotError::Decode(err)
+    }
+}
+
+impl From<serde_json::Error> for BotError {
+    fn from(err: serde_json::Error) -> Self {
+        BotError::Json(err)
+    }
+}
@@ -0,0 +1,111 @@
+use crate::bot::Bot;
+use crate::context::EventContext;
+use crate::error::BotError;
+use crate::onebot::*;
+use async_trait::async_trait;
+
+/// 事件处理器处理完事件后，是否继续交给后面的处理器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Propagation {
+    /// 继续交给优先级更低的处理器
+    Continue,
+    /// 停止传播，后面的处理器不会收到该事件
+    Stop,
+}
+
+/// 处理函数没有返回值时继续传播
+impl From<()> for Propagation {
+    fn from(_: ()) -> Self {
+        Propagation::Continue
+    }
+}
+
+///
+/// 事件处理器，每种事件对应一个方法，默认什么都不做
+///
+/// 只需要实现关心的事件，其余方法保持默认即可，事件和 Bot 都在 EventContext 中
+///
+/// 处理器按 priority 从高到低执行，优先级相同时按注册顺序，返回 Propagation::Stop 后不再交给后面的处理器
+///
+#[async_trait]
+#[allow(unused_variables)]
+pub trait EventHandler: Send + Sync {
+    /// 优先级，越大越先执行，默认 0
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    /// Bot 连接建立
+    async fn on_bot_connected(&self, bot: Bot) {}
+
+    /// Bot 连接断开，此时已经不能调用 API
+    async fn on_bot_disconnected(&self, bot: Bot) {}
+
+    ///
+    /// 收到无法解码的 Frame，连接不会因此断开
+    ///
+    /// @param error BotError::Decode
+    /// @param raw   收到的原始数据
+    ///
+    async fn on_decode_error(&self, bot: Bot, error: &BotError, raw: &[u8]) {}
+
+    /// 私聊消息
+    async fn on_private_message(&self, ctx: &EventContext<PrivateMessageEvent>) -> Propagation {
+        Propagation::Continue
+    }
+
+    /// 群消息
+    async fn on_group_message(&self, ctx: &EventContext<GroupMessageEvent>) -> Propagation {
+        Propagation::Continue
+    }
+
+    /// 群文件上传
+    async fn on_group_upload_notice(&self, ctx: &EventContext<GroupUploadNoticeEvent>) -> Propagation {
+        Propagation::Continue
+    }
+
+    /// 群管理员变动
+    async fn on_group_admin_notice(&self, ctx: &EventContext<GroupAdminNoticeEvent>) -> Propagation {
+        Propagation::Continue
+    }
+
+    /// 群成员减少
+    async fn on_group_decrease_notice(&self, ctx: &EventContext<GroupDecreaseNoticeEvent>) -> Propagation {
+        Propagation::Continue
+    }
+
+    /// 群成员增加
+    async fn on_group_increase_notice(&self, ctx: &EventContext<GroupIncreaseNoticeEvent>) -> Propagation {
+        Propagation::Continue
+    }
+
+    /// 群禁言
+    async fn on_group_ban_notice(&self, ctx: &EventContext<GroupBanNoticeEvent>) -> Propagation {
+        Propagation::Continue
+    }
+
+    /// 好友添加
+    async fn on_friend_add_notice(&self, ctx: &EventContext<FriendAddNoticeEvent>) -> Propagation {
+        Propagation::Continue
+    }
+
+    /// 群消息撤回
+    async fn on_group_recall_notice(&self, ctx: &EventContext<GroupRecallNoticeEvent>) -> Propagation {
+        Propagation::Continue
+    }
+
+    /// 好友消息撤回
+    async fn on_friend_recall_notice(&self, ctx: &EventContext<FriendRecallNoticeEvent>) -> Propagation {
+        Propagation::Continue
+    }
+
+    /// 加好友请求
+    async fn on_friend_request(&self, ctx: &EventContext<FriendRequestEvent>) -> Propagation {
+        Propagation::Continue
+    }
+
+    /// 加群请求／邀请
+    async fn on_group_request(&self, ctx: &EventContext<GroupRequestEvent>) -> Propagation {
+        Propagation::Continue
+    }
+}
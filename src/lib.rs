@@ -1,5 +1,33 @@
+pub mod auth;
 pub mod bot;
+pub mod chain;
+pub mod client;
+pub mod codec;
+mod connection;
+pub mod command;
+pub mod context;
+pub mod dispatcher;
+pub mod error;
+pub mod handler;
+pub mod logging;
+pub mod middleware;
 pub mod msg;
+pub mod options;
+pub mod registry;
+pub mod router;
+pub mod segment;
+pub mod session;
+pub mod server;
+pub mod shutdown;
+#[cfg(test)]
+mod test_util;
+
+pub use auth::Auth;
+pub use client::BotClient;
+pub use options::{BotBuilder, BotOptions};
+pub use registry::BotRegistry;
+pub use router::Router;
+pub use server::BotServer;
 
 pub mod onebot {
     include!(concat!(env!("OUT_DIR"), "/onebot.rs"));
@@ -0,0 +1,74 @@
+use std::fmt;
+use std::str::FromStr;
+use tracing_subscriber::EnvFilter;
+
+/// 日志输出格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// 单行文本
+    #[default]
+    Full,
+    /// 多行文本，便于开发时阅读
+    Pretty,
+    /// 每行一个 JSON 对象，便于日志系统收集
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "full" | "" => Ok(LogFormat::Full),
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unknown log format: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Full => write!(f, "full"),
+            LogFormat::Pretty => write!(f, "pretty"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+///
+/// 初始化全局日志
+///
+/// 过滤规则从 RUST_LOG 读取，例如 RUST_LOG=rs_pbbot_demo=debug，没有设置时使用 default_filter
+///
+/// @param format         输出格式
+/// @param default_filter 默认过滤规则，例如 info
+/// @return 已经初始化过或者过滤规则不合法时返回错误
+///
+pub fn init(format: LogFormat, default_filter: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(default_filter)?,
+    };
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Full => builder.try_init(),
+        LogFormat::Pretty => builder.pretty().try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_log_format() {
+        assert_eq!("JSON".parse(), Ok(LogFormat::Json));
+        assert_eq!("pretty".parse(), Ok(LogFormat::Pretty));
+        assert_eq!("".parse(), Ok(LogFormat::Full));
+        assert!("xml".parse::<LogFormat>().is_err());
+        assert_eq!(LogFormat::Json.to_string().parse(), Ok(LogFormat::Json));
+    }
+}
@@ -1,146 +1,66 @@
-//! Example chat application.
+//! Example bot.
 //!
 //! Run with
 //!
 //! ```not_rust
-//! cargo run -p example-chat
+//! cargo run
 //! ```
+//!
+//! 日志格式用 LOG_FORMAT 设置（full、pretty、json），过滤规则用 RUST_LOG 设置
+//!
+//! 设置 WS_URL 和 SELF_ID 时使用正向 websocket 主动连接，ACCESS_TOKEN 可选
 
-use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
-use axum::extract::{Extension, RequestParts, TypedHeader};
-use axum::handler::get;
-use axum::http::header::HeaderMap;
-use axum::response::{Html, IntoResponse};
-use axum::{AddExtensionLayer, Error};
-use axum::Router;
-use futures::{sink::SinkExt, stream::StreamExt, Sink};
-use std::collections::{HashSet, HashMap};
-use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::sync::{broadcast, oneshot, Mutex};
-use headers::HeaderMapExt;
-use futures::stream::SplitSink;
-use tokio::sync::mpsc;
-use axum::http::{StatusCode, Response, HeaderValue};
-use prost;
-use onebot::*;
-use std::convert::Infallible;
-use prost::DecodeError;
-use rs_pbbot_demo::onebot::frame::{Data, FrameType};
+use async_trait::async_trait;
 use rs_pbbot_demo::onebot::*;
-use rs_pbbot_demo::onebot;
-use rs_pbbot_demo::bot::Bot;
+use rs_pbbot_demo::context::EventContext;
+use rs_pbbot_demo::handler::{EventHandler, Propagation};
+use rs_pbbot_demo::logging::{self, LogFormat};
 use rs_pbbot_demo::msg::*;
-use tokio::sync::mpsc::Sender;
-use uuid::Uuid;
-use std::ops::Deref;
+use rs_pbbot_demo::{BotBuilder, BotClient, BotServer};
 
 
 #[tokio::main]
 async fn main() {
-    let app = Router::new()
-        .route("/ws/cq/", get(websocket_handler));
+    let format: LogFormat = std::env::var("LOG_FORMAT").unwrap_or_default().parse().unwrap_or_default();
+    logging::init(format, "info").unwrap();
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 8081));
+    if let Ok(url) = std::env::var("WS_URL") {
+        let self_id = std::env::var("SELF_ID").ok().and_then(|id| id.parse().ok()).expect("SELF_ID is required");
+        let mut client = BotClient::new(&url, self_id).handler(DemoHandler);
+        if let Ok(token) = std::env::var("ACCESS_TOKEN") {
+            client = client.access_token(&token);
+        }
+        client.run().await.unwrap();
+        return;
+    }
 
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+    BotServer::new()
+        .bind(([127, 0, 0, 1], 8081))
+        .path("/ws/cq/")
+        .handler(DemoHandler)
+        .run()
         .await
         .unwrap();
 }
 
-async fn websocket_handler(
-    ws: WebSocketUpgrade,
-    headers: HeaderMap,
-) -> impl IntoResponse {
-    let bot_id = headers.get("x-self-id").map(|id| id.to_str().unwrap().parse().unwrap_or_default()).unwrap_or_default();
-    ws.on_upgrade(move |socket| websocket(socket, bot_id))
-}
+struct DemoHandler;
 
-async fn websocket(stream: WebSocket, bot_id: i64) {
-    if bot_id == 0 {
-        stream.close();
-        return;
-    }
-    println!("bot connected: {}", bot_id);
-    let (mut ws_out, mut ws_in) = stream.split();
-    let (api_sender, mut api_receiver) = mpsc::channel(10); // api channel
-    let resp_promises = Arc::new(Mutex::new(HashMap::new()));
-    let mut bot = Bot { bot_id, api_sender: mpsc::Sender::clone(&api_sender), resp_promises: resp_promises.clone() };
-
-    // 发送 api req
-    let mut send_task = tokio::spawn(async move {
-        while let Some(frame) = api_receiver.recv().await {
-            let mut buf = Vec::new();
-            prost::Message::encode(&frame, &mut buf);
-            if ws_out.send(Message::Binary(buf)).await.is_err() {
-                break;
-            }
+#[async_trait]
+impl EventHandler for DemoHandler {
+    async fn on_private_message(&self, ctx: &EventContext<PrivateMessageEvent>) -> Propagation {
+        // let reply_msg = share("https://www.baidu.com/", "百度", "baidu", "https://www.baidu.com/img/PCtm_d9c8750bed0b3c7d089fa7d55720d6cf.png");
+        let reply_msg = text("hello") + face(1);
+        let mut bot = ctx.bot.clone();
+        if let Ok(message_id) = ctx.reply_quoted(reply_msg).await {
+            tracing::info!(message_id, "replied");
+            tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+            let _ = bot.delete_msg(message_id).await;
         }
-    });
-
-    // 接受 event 和 api resp
-    let mut recv_task = tokio::spawn(async move {
-        while let Some(Ok(ws_message)) = ws_in.next().await {
-            match ws_message {
-                Message::Binary(buf) => {
-                    let frame: onebot::Frame = match prost::Message::decode(buf.as_ref()) {
-                        Ok(frame) => { frame }
-                        Err(_) => break
-                    };
-                    if let Some(data) = frame.clone().data {
-                        let mut bot = bot.clone();
-                        tokio::spawn(async move {
-                            match data {
-                                Data::PrivateMessageEvent(event) => {
-                                    // let reply_msg = share("https://www.baidu.com/", "百度", "baidu", "https://www.baidu.com/img/PCtm_d9c8750bed0b3c7d089fa7d55720d6cf.png");
-                                    let reply_msg = text("hello") + face(1);
-                                    let resp = bot.send_private_message(event.user_id, reply_msg).await;
-                                    if let Some(resp) = resp {
-                                        println!("message_id: {}", resp.message_id);
-                                        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-                                        bot.delete_msg(resp.message_id).await;
-                                    }
-                                    if let Some(get_group_list_resp) = bot.get_group_list().await {
-                                        for group in get_group_list_resp.group {
-                                            println!("{} {}", group.group_id, group.group_name)
-                                        }
-                                    }
-                                }
-                                Data::GroupMessageEvent(event) => {}
-                                Data::GroupUploadNoticeEvent(event) => {}
-                                Data::GroupAdminNoticeEvent(event) => {}
-                                Data::GroupDecreaseNoticeEvent(event) => {}
-                                Data::GroupIncreaseNoticeEvent(event) => {}
-                                Data::GroupBanNoticeEvent(event) => {}
-                                Data::FriendAddNoticeEvent(event) => {}
-                                Data::GroupRecallNoticeEvent(event) => {}
-                                Data::FriendRecallNoticeEvent(event) => {}
-                                Data::FriendRequestEvent(event) => {}
-                                Data::GroupRequestEvent(event) => {}
-                                _ => {
-                                    // 不是 event，一定是 api resp
-                                    if let Some(api_resp_sender) = bot.resp_promises.lock().await.remove(frame.echo.clone().as_str()) {
-                                        api_resp_sender.send(frame);
-                                    }
-                                }
-                            }
-                        });
-                    }
-                }
-                Message::Close(_) => { break; }
-                _ => {}
+        if let Ok(get_group_list_resp) = bot.get_group_list().await {
+            for group in get_group_list_resp.group {
+                tracing::info!(group.group_id, %group.group_name, "group")
             }
         }
-    });
-
-    tokio::select! {
-        _ = (&mut send_task) => recv_task.abort(),
-        _ = (&mut recv_task) => send_task.abort(),
+        Propagation::Continue
     }
-    ;
-    println!("bot disconnected: {}", bot_id);
 }
-
-
-
@@ -0,0 +1,223 @@
+use crate::bot::Bot;
+use crate::dispatcher::Dispatcher;
+use crate::error::BotError;
+use crate::onebot::Frame;
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use std::sync::Arc;
+use std::time::Duration;
+
+///
+/// 中间件，包在收到的事件和发出的 API 调用外面，用于日志、黑名单、限流、统计等
+///
+/// 和 tower 的 Service 类似，中间件按注册顺序从外到内执行，调用 next.run(frame) 交给下一层。
+/// 在调用前后可以检查、修改 Frame，也可以 sleep 延迟，不调用 next 则请求被拦截
+///
+/// struct Blacklist(HashSet<i64>);
+///
+/// #[async_trait]
+/// impl Middleware for Blacklist {
+///     async fn on_event(&self, bot: &Bot, frame: Frame, next: EventNext<'_>) {
+///         if !self.0.contains(&sender_of(&frame)) {
+///             next.run(frame).await
+///         }
+///     }
+/// }
+///
+#[async_trait]
+#[allow(unused_variables)]
+pub trait Middleware: Send + Sync {
+    /// 收到事件，最内层是 Dispatcher
+    async fn on_event(&self, bot: &Bot, frame: Frame, next: EventNext<'_>) {
+        next.run(frame).await
+    }
+
+    ///
+    /// 调用 API，最内层发送请求并等待响应
+    ///
+    /// 拦截时返回 Err，或者直接返回构造的响应 Frame
+    ///
+    async fn on_call(&self, bot: &Bot, frame: Frame, next: CallNext<'_>) -> Result<Frame, BotError> {
+        next.run(frame).await
+    }
+}
+
+/// 一个连接上的中间件，按注册顺序从外到内
+pub type Middlewares = Arc<Vec<Arc<dyn Middleware>>>;
+
+/// 事件中间件链的剩余部分
+pub struct EventNext<'a> {
+    pub(crate) bot: &'a Bot,
+    pub(crate) middlewares: &'a [Arc<dyn Middleware>],
+    pub(crate) dispatcher: &'a Dispatcher,
+}
+
+impl<'a> EventNext<'a> {
+    /// 交给下一个中间件，没有中间件时交给 Dispatcher
+    pub fn run(self, frame: Frame) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            match self.middlewares.split_first() {
+                Some((middleware, rest)) => {
+                    let next = EventNext { middlewares: rest, ..self };
+                    middleware.on_event(self.bot, frame, next).await
+                }
+                None => {
+                    if let Some(data) = frame.data {
+                        self.dispatcher.dispatch(self.bot.clone(), &data).await;
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// API 调用中间件链的剩余部分
+pub struct CallNext<'a> {
+    pub(crate) bot: &'a Bot,
+    pub(crate) middlewares: &'a [Arc<dyn Middleware>],
+    pub(crate) timeout: Duration,
+}
+
+impl<'a> CallNext<'a> {
+    /// 交给下一个中间件，没有中间件时发送请求并等待响应，超时时间不包括中间件的延迟
+    pub fn run(self, frame: Frame) -> BoxFuture<'a, Result<Frame, BotError>> {
+        Box::pin(async move {
+            match self.middlewares.split_first() {
+                Some((middleware, rest)) => {
+                    let next = CallNext { middlewares: rest, ..self };
+                    middleware.on_call(self.bot, frame, next).await
+                }
+                None => self.bot.send_frame(frame, self.timeout).await,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::EventContext;
+    use crate::handler::{EventHandler, Propagation};
+    use crate::msg::text;
+    use crate::onebot::frame::Data;
+    use crate::onebot::*;
+    use std::sync::Mutex;
+    use tokio::sync::mpsc;
+
+    /// 记录经过的顺序
+    struct Trace(&'static str, Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl Middleware for Trace {
+        async fn on_event(&self, _bot: &Bot, frame: Frame, next: EventNext<'_>) {
+            self.1.lock().unwrap().push(format!("{} before", self.0));
+            next.run(frame).await;
+            self.1.lock().unwrap().push(format!("{} after", self.0));
+        }
+    }
+
+    /// 拦截 user_id 为 1 的消息
+    struct Blacklist;
+
+    #[async_trait]
+    impl Middleware for Blacklist {
+        async fn on_event(&self, _bot: &Bot, frame: Frame, next: EventNext<'_>) {
+            if let Some(Data::GroupMessageEvent(event)) = &frame.data {
+                if event.user_id == 1 {
+                    return;
+                }
+            }
+            next.run(frame).await
+        }
+    }
+
+    /// 给发出的群消息加上后缀，get_login_info 直接返回
+    struct Rewrite;
+
+    #[async_trait]
+    impl Middleware for Rewrite {
+        async fn on_call(&self, _bot: &Bot, mut frame: Frame, next: CallNext<'_>) -> Result<Frame, BotError> {
+            match &mut frame.data {
+                Some(Data::SendGroupMsgReq(req)) => req.message.push(text("~")),
+                Some(Data::GetLoginInfoReq(_)) => {
+                    return Ok(Frame {
+                        ok: true,
+                        data: Some(Data::GetLoginInfoResp(GetLoginInfoResp { user_id: 10001, nickname: "cached".to_string() })),
+                        ..Default::default()
+                    });
+                }
+                _ => {}
+            }
+            next.run(frame).await
+        }
+    }
+
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl EventHandler for Recorder {
+        async fn on_group_message(&self, ctx: &EventContext<GroupMessageEvent>) -> Propagation {
+            self.0.lock().unwrap().push(format!("handler {}", ctx.user_id));
+            Propagation::Continue
+        }
+    }
+
+    fn group_message(user_id: i64) -> Frame {
+        Frame {
+            data: Some(Data::GroupMessageEvent(GroupMessageEvent { group_id: 100, user_id, ..Default::default() })),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn event_middlewares_wrap_dispatcher_in_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let (api_sender, _api_receiver) = mpsc::channel(10);
+        let bot = Bot::new(10001, api_sender).with_middlewares(Arc::new(vec![
+            Arc::new(Trace("outer", log.clone())),
+            Arc::new(Blacklist),
+            Arc::new(Trace("inner", log.clone())),
+        ]));
+        let dispatcher = Dispatcher::new().add_handler(Recorder(log.clone()));
+
+        dispatcher.dispatch_frame(bot.clone(), group_message(2)).await;
+        dispatcher.dispatch_frame(bot, group_message(1)).await;
+
+        assert_eq!(*log.lock().unwrap(), vec![
+            "outer before", "inner before", "handler 2", "inner after", "outer after",
+            "outer before", "outer after",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn call_middleware_modifies_and_short_circuits() {
+        let (api_sender, mut api_receiver) = mpsc::channel::<Frame>(10);
+        let mut bot = Bot::new(10001, api_sender).with_middlewares(Arc::new(vec![Arc::new(Rewrite)]));
+
+        let resp = bot.get_login_info().await.unwrap();
+        assert_eq!(resp.nickname, "cached");
+
+        let responder = bot.clone();
+        let peer = tokio::spawn(async move {
+            let frame = api_receiver.recv().await.unwrap();
+            let data = frame.data.clone();
+            responder.handle_response(Frame {
+                echo: frame.echo,
+                ok: true,
+                data: Some(Data::SendGroupMsgResp(SendGroupMsgResp { message_id: 1 })),
+                ..Default::default()
+            });
+            drop(responder);
+            // get_login_info 没有发出请求
+            assert!(api_receiver.recv().await.is_none());
+            data
+        });
+        bot.send_group_message(100, text("hi")).await.unwrap();
+        drop(bot);
+        assert_eq!(peer.await.unwrap(), Some(Data::SendGroupMsgReq(SendGroupMsgReq {
+            group_id: 100,
+            message: vec![text("hi"), text("~")],
+            ..Default::default()
+        })));
+    }
+}
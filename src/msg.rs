@@ -1,184 +1,301 @@
 use crate::onebot::Message;
-use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::collections::HashMap;
 use std::iter::FromIterator;
-use std::array::IntoIter;
 use std::ops::Add;
-use std::str::FromStr;
-use std::ops::Deref;
 
 pub fn text(text: &str) -> Message {
-    return Message {
+    Message {
         r#type: "text".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("text".to_string(), text.to_string()),
         ])),
-    };
+    }
 }
 
 pub fn image(url: &str) -> Message {
-    return Message {
+    Message {
         r#type: "image".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("url".to_string(), url.to_string()),
         ])),
-    };
+    }
 }
 
 pub fn record(url: &str) -> Message {
-    return Message {
+    Message {
         r#type: "record".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("url".to_string(), url.to_string()),
         ])),
-    };
+    }
 }
 
 pub fn flash(url: &str) -> Message {
-    return Message {
+    Message {
         r#type: "image".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("url".to_string(), url.to_string()),
             ("type".to_string(), "flash".to_string()),
         ])),
-    };
+    }
 }
 
 pub fn show(url: &str, effect_id: i32) -> Message {
-    return Message {
+    Message {
         r#type: "image".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("url".to_string(), url.to_string()),
             ("type".to_string(), "show".to_string()),
             ("effect_id".to_string(), effect_id.to_string()),
         ])),
-    };
+    }
 }
 
 pub fn at(qq: i64) -> Message {
-    return Message {
+    Message {
         r#type: "at".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
-            ("at".to_string(), qq.to_string()),
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
+            ("qq".to_string(), qq.to_string()),
         ])),
-    };
+    }
 }
 
 pub fn at_all() -> Message {
-    return Message {
+    Message {
         r#type: "at".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("qq".to_string(), "all".to_string()),
         ])),
-    };
+    }
 }
 
 pub fn face(id: i32) -> Message {
-    return Message {
+    Message {
         r#type: "face".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("id".to_string(), id.to_string()),
         ])),
-    };
+    }
 }
 
 pub fn poke(qq: i64) -> Message {
-    return Message {
+    Message {
         r#type: "poke".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("qq".to_string(), qq.to_string()),
         ])),
-    };
+    }
 }
 
 pub fn share(url: &str, title: &str, content: &str, image: &str) -> Message {
-    return Message {
+    Message {
         r#type: "share".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("url".to_string(), url.to_string()),
             ("title".to_string(), title.to_string()),
             ("content".to_string(), content.to_string()),
             ("image".to_string(), image.to_string()),
         ])),
-    };
+    }
 }
 
 pub fn light_app(content: &str) -> Message {
-    return Message {
+    Message {
         r#type: "light_app".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("content".to_string(), content.to_string()),
         ])),
-    };
+    }
 }
 
 pub fn xml(id: i32, content: &str) -> Message {
-    return Message {
+    Message {
         r#type: "service".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("sub_type".to_string(), "xml".to_string()),
             ("id".to_string(), id.to_string()),
             ("content".to_string(), content.to_string()),
         ])),
-    };
+    }
 }
 
 pub fn json(id: i32, content: &str) -> Message {
-    return Message {
+    Message {
         r#type: "service".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("sub_type".to_string(), "json".to_string()),
             ("id".to_string(), id.to_string()),
             ("content".to_string(), content.to_string()),
         ])),
-    };
+    }
 }
 
 pub fn reply(message_id: i32) -> Message {
-    return Message {
+    Message {
         r#type: "reply".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("message_id".to_string(), message_id.to_string()),
         ])),
-    };
+    }
 }
 
 pub fn sleep(time: i64) -> Message {
-    return Message {
+    Message {
         r#type: "sleep".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("time".to_string(), time.to_string()),
         ])),
-    };
+    }
 }
 
 pub fn tts(text: &str) -> Message {
-    return Message {
+    Message {
         r#type: "tts".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("text".to_string(), text.to_string()),
         ])),
-    };
+    }
 }
 
 pub fn video(url: &str, cover: &str, cache: bool) -> Message {
-    return Message {
+    Message {
         r#type: "video".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("url".to_string(), url.to_string()),
             ("cover".to_string(), cover.to_string()),
             ("cache".to_string(), if cache { "1".to_string() } else { "0".to_string() }),
         ])),
-    };
+    }
 }
 
 pub fn gift(qq: i64, id: i32) -> Message {
-    return Message {
+    Message {
         r#type: "gift".to_string(),
-        data: HashMap::<_, _>::from_iter(IntoIter::new([
+        data: HashMap::<_, _>::from_iter(IntoIterator::into_iter([
             ("qq".to_string(), qq.to_string()),
             ("id".to_string(), id.to_string()),
         ])),
-    };
+    }
+}
+
+///
+/// 解析 CQ 码，例如 [CQ:at,qq=123]hello[CQ:face,id=1]
+///
+/// 不完整或格式错误的 CQ 码按普通文本处理
+///
+pub fn parse_cq(cq: &str) -> Vec<Message> {
+    let mut messages = Vec::new();
+    let mut raw_text = String::new();
+    let mut rest = cq;
+    while let Some(start) = rest.find("[CQ:") {
+        let len = match rest[start..].find(']') {
+            Some(len) => len,
+            None => break,
+        };
+        match parse_cq_code(&rest[start + 4..start + len]) {
+            Some(message) => {
+                raw_text.push_str(&rest[..start]);
+                push_cq_text(&mut messages, &raw_text);
+                raw_text.clear();
+                messages.push(message);
+                rest = &rest[start + len + 1..];
+            }
+            None => {
+                // 从下一个 [CQ: 开始重新查找，避免吞掉后面合法的 CQ 码
+                raw_text.push_str(&rest[..start + 4]);
+                rest = &rest[start + 4..];
+            }
+        }
+    }
+    raw_text.push_str(rest);
+    push_cq_text(&mut messages, &raw_text);
+    messages
+}
+
+///
+/// 把消息转换为 CQ 码，参数按 key 排序
+///
+pub fn to_cq(messages: &[Message]) -> String {
+    let mut cq = String::new();
+    for message in messages {
+        if message.r#type == "text" {
+            cq.push_str(&escape_cq(message.data.get("text").map(String::as_str).unwrap_or_default(), false));
+            continue;
+        }
+        cq.push_str("[CQ:");
+        cq.push_str(&message.r#type);
+        let mut data: Vec<_> = message.data.iter().collect();
+        data.sort();
+        for (key, value) in data {
+            cq.push(',');
+            cq.push_str(key);
+            cq.push('=');
+            cq.push_str(&escape_cq(value, true));
+        }
+        cq.push(']');
+    }
+    cq
+}
+
+/// "at,qq=123" -> at 消息段，类型、key 和 value 中出现未转义的特殊字符时返回 None
+fn parse_cq_code(code: &str) -> Option<Message> {
+    let is_name = |name: &str| !name.is_empty() && !name.contains(&['=', '[', ']', ',', '&'][..]);
+    let mut parts = code.split(',');
+    let r#type = parts.next().filter(|t| is_name(t))?;
+    let mut data = HashMap::new();
+    for part in parts {
+        let (key, value) = part.split_once('=').filter(|(key, value)| is_name(key) && !value.contains('['))?;
+        data.insert(key.to_string(), unescape_cq(value));
+    }
+    Some(Message {
+        r#type: r#type.to_string(),
+        data,
+    })
+}
+
+fn push_cq_text(messages: &mut Vec<Message>, raw_text: &str) {
+    if !raw_text.is_empty() {
+        messages.push(text(&unescape_cq(raw_text)));
+    }
+}
+
+/// 文本中转义 & [ ]，CQ 码参数中额外转义 ,
+fn escape_cq(s: &str, is_param: bool) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '[' => escaped.push_str("&#91;"),
+            ']' => escaped.push_str("&#93;"),
+            ',' if is_param => escaped.push_str("&#44;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape_cq(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let (c, len) = if rest.starts_with("&amp;") {
+            ('&', 5)
+        } else if rest.starts_with("&#91;") {
+            ('[', 5)
+        } else if rest.starts_with("&#93;") {
+            (']', 5)
+        } else if rest.starts_with("&#44;") {
+            (',', 5)
+        } else {
+            ('&', 1)
+        };
+        unescaped.push(c);
+        rest = &rest[len..];
+    }
+    unescaped.push_str(rest);
+    unescaped
 }
 
 impl From<Message> for Vec<Message> {
@@ -251,7 +368,7 @@ impl Add<&Vec<Message>> for &Message {
         for msg in rhs {
             ret.push(msg.clone())
         }
-        return ret;
+        ret
     }
 }
 
@@ -285,6 +402,94 @@ impl Add<&Message> for &Vec<Message> {
     fn add(self, rhs: &Message) -> Self::Output {
         let mut ret: Vec<Message> = self.clone();
         ret.push(rhs.clone());
-        return ret;
+        ret
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn parse_cq_code() {
+        let messages = parse_cq("[CQ:at,qq=123]hello[CQ:face,id=1]");
+        assert_eq!(messages, vec![
+            Message { r#type: "at".to_string(), data: HashMap::from_iter(vec![("qq".to_string(), "123".to_string())]) },
+            text("hello"),
+            face(1),
+        ]);
+    }
+
+    #[test]
+    fn cq_escape() {
+        let messages = vec![text("a&b[c]d,e"), share("http://a/?x=1&y=2", "t,i[t]le", "", "")];
+        let cq = to_cq(&messages);
+        assert_eq!(cq, "a&amp;b&#91;c&#93;d,e[CQ:share,content=,image=,title=t&#44;i&#91;t&#93;le,url=http://a/?x=1&amp;y=2]");
+        assert_eq!(parse_cq(&cq), messages);
+    }
+
+    #[test]
+    fn malformed_cq_is_text() {
+        assert_eq!(parse_cq("[CQ:at,qq]x"), vec![text("[CQ:at,qq]x")]);
+        assert_eq!(parse_cq("[CQ:,a=1]"), vec![text("[CQ:,a=1]")]);
+        assert_eq!(parse_cq("a[CQ:face,id=1"), vec![text("a[CQ:face,id=1")]);
+        assert_eq!(parse_cq("&foo;"), vec![text("&foo;")]);
+        assert_eq!(parse_cq("[CQ:a[CQ:face,id=1]"), vec![text("[CQ:a"), face(1)]);
+        assert_eq!(parse_cq("[CQ:face,id=[CQ:face,id=1]x"), vec![text("[CQ:face,id="), face(1), text("x")]);
+        assert_eq!(parse_cq("[CQ:at,q&q=1]"), vec![text("[CQ:at,q&q=1]")]);
+        assert_eq!(parse_cq(""), vec![]);
+    }
+
+    /// 连续的文本合并、空文本去掉后的消息，和 parse_cq 的输出形式一致
+    fn normalize(messages: Vec<Message>) -> Vec<Message> {
+        let mut normalized: Vec<Message> = Vec::new();
+        for message in messages {
+            if message.r#type == "text" {
+                let t = message.data.get("text").cloned().unwrap_or_default();
+                if t.is_empty() {
+                    continue;
+                }
+                if let Some(last) = normalized.last_mut().filter(|last| last.r#type == "text") {
+                    last.data.get_mut("text").unwrap().push_str(&t);
+                    continue;
+                }
+                normalized.push(text(&t));
+            } else {
+                normalized.push(message);
+            }
+        }
+        normalized
+    }
+
+    fn arb_message() -> impl Strategy<Value = Message> {
+        prop_oneof![
+            any::<String>().prop_map(|t| text(&t)),
+            (
+                "[a-z_]{1,8}".prop_filter("text", |t| t != "text"),
+                proptest::collection::hash_map("[a-z_]{1,8}", any::<String>(), 0..4),
+            ).prop_map(|(r#type, data)| Message { r#type, data }),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn messages_round_trip(messages in proptest::collection::vec(arb_message(), 0..8)) {
+            let cq = to_cq(&messages);
+            prop_assert_eq!(parse_cq(&cq), normalize(messages));
+        }
+
+        #[test]
+        fn parse_is_stable(cq in any::<String>()) {
+            let messages = parse_cq(&cq);
+            prop_assert_eq!(parse_cq(&to_cq(&messages)), messages);
+        }
+
+        #[test]
+        fn parse_is_stable_on_cq_like_input(cq in "([a-z&#;,=\\[\\]]|\\[CQ:|&amp;|&#9[13];|&#44;){0,32}") {
+            let messages = parse_cq(&cq);
+            prop_assert_eq!(parse_cq(&to_cq(&messages)), messages);
+        }
     }
 }
@@ -0,0 +1,137 @@
+use crate::connection::{ConnectionConfig, DEFAULT_MAX_DECODE_ERRORS};
+use crate::dispatcher::Dispatcher;
+use crate::handler::EventHandler;
+use crate::middleware::Middleware;
+use crate::registry::BotRegistry;
+use crate::shutdown::{self, ShutdownSignal};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+///
+/// BotServer 和 BotClient 共用的配置：事件处理器、中间件、解码失败和退出的处理方式
+///
+pub struct BotOptions {
+    dispatcher: Dispatcher,
+    registry: BotRegistry,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    shutdown_signal: Option<ShutdownSignal>,
+    shutdown_timeout: Duration,
+    max_decode_errors: u32,
+}
+
+impl Default for BotOptions {
+    fn default() -> Self {
+        BotOptions {
+            dispatcher: Dispatcher::new(),
+            registry: BotRegistry::new(),
+            middlewares: Vec::new(),
+            shutdown_signal: None,
+            shutdown_timeout: shutdown::DEFAULT_SHUTDOWN_TIMEOUT,
+            max_decode_errors: DEFAULT_MAX_DECODE_ERRORS,
+        }
+    }
+}
+
+impl BotOptions {
+    /// 退出时最多等待事件处理器和 API 调用多久
+    pub(crate) fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout
+    }
+
+    ///
+    /// 创建所有连接共享的配置
+    ///
+    /// @return 连接配置，以及在 shutdown_signal 完成后通知连接开始退出的 future
+    ///
+    pub(crate) fn start(self) -> (ConnectionConfig, ShutdownSignal) {
+        let (shutdown_sender, shutdown_receiver) = watch::channel(None);
+        let config = ConnectionConfig {
+            dispatcher: self.dispatcher,
+            registry: self.registry,
+            middlewares: Arc::new(self.middlewares),
+            shutdown: shutdown_receiver,
+            max_decode_errors: self.max_decode_errors,
+        };
+        let shutdown_timeout = self.shutdown_timeout;
+        let signal = self.shutdown_signal.unwrap_or_else(|| Box::pin(shutdown::signal()));
+        (config, Box::pin(async move {
+            signal.await;
+            let _ = shutdown_sender.send(Some(Instant::now() + shutdown_timeout));
+        }))
+    }
+}
+
+///
+/// BotServer 和 BotClient 共用的构造方法，修改各自的 BotOptions
+///
+/// use rs_pbbot_demo::BotBuilder;
+///
+/// BotServer::new().handler(MyHandler).shutdown_timeout(Duration::from_secs(5))
+///
+pub trait BotBuilder: Sized {
+    /// 共用的配置
+    fn options_mut(&mut self) -> &mut BotOptions;
+
+    /// 注册事件处理器
+    fn handler<H: EventHandler + 'static>(mut self, handler: H) -> Self {
+        let options = self.options_mut();
+        options.dispatcher = std::mem::take(&mut options.dispatcher).add_handler(handler);
+        self
+    }
+
+    /// 使用指定优先级注册事件处理器，越大越先执行
+    fn handler_with_priority<H: EventHandler + 'static>(mut self, handler: H, priority: i32) -> Self {
+        let options = self.options_mut();
+        options.dispatcher = std::mem::take(&mut options.dispatcher).add_handler_with_priority(handler, priority);
+        self
+    }
+
+    /// 使用已经配置好的 Dispatcher，会替换之前注册的事件处理器
+    fn dispatcher(mut self, dispatcher: Dispatcher) -> Self {
+        self.options_mut().dispatcher = dispatcher;
+        self
+    }
+
+    /// 使用外部创建的 BotRegistry，便于在其他 task 中按 bot_id 获取 Bot
+    fn registry(mut self, registry: BotRegistry) -> Self {
+        self.options_mut().registry = registry;
+        self
+    }
+
+    /// 添加中间件，先添加的在外层，所有连接共用
+    fn middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.options_mut().middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    ///
+    /// 连续多少个 Frame 解码失败后断开连接，默认 10，0 表示不断开
+    ///
+    /// 解码失败的 Frame 会被记录日志并交给 EventHandler::on_decode_error，连接继续保持。
+    /// BotClient 断开后会重连
+    ///
+    fn max_decode_errors(mut self, max_decode_errors: u32) -> Self {
+        self.options_mut().max_decode_errors = max_decode_errors;
+        self
+    }
+
+    ///
+    /// signal 完成后开始退出，默认为收到 SIGINT 或 SIGTERM
+    ///
+    /// 退出时停止接受新连接和新事件，等待正在执行的事件处理器和 API 调用完成，
+    /// 然后向每个连接发送 Close 帧。BotClient 不再重连
+    ///
+    fn shutdown_signal<F: Future<Output = ()> + Send + 'static>(mut self, signal: F) -> Self {
+        self.options_mut().shutdown_signal = Some(Box::pin(signal));
+        self
+    }
+
+    /// 退出时最多等待事件处理器和 API 调用多久，默认 10 秒
+    fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.options_mut().shutdown_timeout = timeout;
+        self
+    }
+}
@@ -0,0 +1,118 @@
+use crate::bot::Bot;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+///
+/// 在线 Bot 注册表，按 bot_id 查找，可以在任意 task 中使用
+///
+/// 多个账号连接同一个服务器时，通过 registry 获取其他账号的 Bot
+///
+#[derive(Clone, Default)]
+pub struct BotRegistry {
+    bots: Arc<RwLock<HashMap<i64, Bot>>>,
+}
+
+impl BotRegistry {
+    pub fn new() -> BotRegistry {
+        Default::default()
+    }
+
+    ///
+    /// 获取在线的 Bot
+    ///
+    /// @param bot_id 机器人 QQ 号
+    /// @return 不在线返回 None
+    ///
+    pub fn get(&self, bot_id: i64) -> Option<Bot> {
+        self.bots.read().ok()?.get(&bot_id).cloned()
+    }
+
+    /// 所有在线的 bot_id
+    pub fn bot_ids(&self) -> Vec<i64> {
+        match self.bots.read() {
+            Ok(bots) => bots.keys().copied().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// 所有在线的 Bot
+    pub fn bots(&self) -> Vec<Bot> {
+        match self.bots.read() {
+            Ok(bots) => bots.values().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn is_online(&self, bot_id: i64) -> bool {
+        self.get(bot_id).is_some()
+    }
+
+    /// 连接建立时调用，同一个 bot_id 重复连接时替换旧连接
+    pub(crate) fn connect(&self, bot: Bot) {
+        if let Ok(mut bots) = self.bots.write() {
+            bots.insert(bot.bot_id, bot);
+        }
+    }
+
+    /// 连接断开时调用，只移除属于这个连接的 Bot，不影响之后的新连接
+    pub(crate) fn disconnect(&self, bot: &Bot) {
+        if let Ok(mut bots) = self.bots.write() {
+            let same_connection = bots.get(&bot.bot_id)
+                .map(|current| current.api_sender.same_channel(&bot.api_sender))
+                .unwrap_or(false);
+            if same_connection {
+                bots.remove(&bot.bot_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    fn bot(bot_id: i64) -> Bot {
+        let (api_sender, _api_receiver) = mpsc::channel(1);
+        Bot::new(bot_id, api_sender)
+    }
+
+    #[test]
+    fn connect_and_disconnect() {
+        let registry = BotRegistry::new();
+        assert!(!registry.is_online(10001));
+        assert!(registry.get(10001).is_none());
+
+        let first = bot(10001);
+        registry.connect(first.clone());
+        registry.connect(bot(10002));
+        assert!(registry.is_online(10001));
+        assert!(registry.get(10001).unwrap().api_sender.same_channel(&first.api_sender));
+        let mut bot_ids = registry.bot_ids();
+        bot_ids.sort_unstable();
+        assert_eq!(bot_ids, vec![10001, 10002]);
+        assert_eq!(registry.bots().len(), 2);
+
+        registry.disconnect(&first);
+        assert!(!registry.is_online(10001));
+        assert!(registry.is_online(10002));
+    }
+
+    #[test]
+    fn reconnect_replaces_and_stale_disconnect_is_ignored() {
+        let registry = BotRegistry::new();
+        let old = bot(10001);
+        let new = bot(10001);
+        registry.connect(old.clone());
+        registry.connect(new.clone());
+        assert_eq!(registry.bot_ids(), vec![10001]);
+        assert!(registry.get(10001).unwrap().api_sender.same_channel(&new.api_sender));
+
+        // 旧连接晚于新连接断开，不能移除新连接
+        registry.disconnect(&old);
+        assert!(registry.get(10001).unwrap().api_sender.same_channel(&new.api_sender));
+
+        registry.disconnect(&new);
+        assert!(!registry.is_online(10001));
+    }
+}
@@ -0,0 +1,547 @@
+use crate::chain::MessageChain;
+use crate::context::{Event, EventContext};
+use crate::handler::{EventHandler, Propagation};
+use crate::onebot::*;
+use crate::session::MessageEvent;
+use async_trait::async_trait;
+use regex::Regex;
+use std::collections::HashSet;
+use std::future::Future;
+use std::ops::Not;
+use std::sync::{Arc, RwLock};
+
+/// 有消息内容的事件
+pub trait HasMessage: Event {
+    fn message(&self) -> &[Message];
+}
+
+impl HasMessage for PrivateMessageEvent {
+    fn message(&self) -> &[Message] {
+        &self.message
+    }
+}
+
+impl HasMessage for GroupMessageEvent {
+    fn message(&self) -> &[Message] {
+        &self.message
+    }
+}
+
+impl HasMessage for MessageEvent {
+    fn message(&self) -> &[Message] {
+        MessageEvent::message(self)
+    }
+}
+
+/// 有 sub_type 的事件
+pub trait HasSubType: Event {
+    fn sub_type(&self) -> &str;
+}
+
+macro_rules! impl_sub_type {
+    ($($event:ty),*) => {
+        $(impl HasSubType for $event {
+            fn sub_type(&self) -> &str {
+                &self.sub_type
+            }
+        })*
+    };
+}
+
+impl_sub_type!(
+    PrivateMessageEvent,
+    GroupMessageEvent,
+    GroupAdminNoticeEvent,
+    GroupDecreaseNoticeEvent,
+    GroupIncreaseNoticeEvent,
+    GroupBanNoticeEvent,
+    GroupRequestEvent
+);
+
+fn plain_text(message: &[Message]) -> String {
+    MessageChain::from(message.to_vec()).plain_text()
+}
+
+///
+/// 事件过滤条件，可以用 and、or、! 组合
+///
+/// let admin_command = prefix("/").and(role("admin").or(role("owner")));
+/// let not_blocked = !from_users([123456]);
+///
+pub struct Predicate<E>(Arc<dyn Fn(&E) -> bool + Send + Sync>);
+
+impl<E> Clone for Predicate<E> {
+    fn clone(&self) -> Self {
+        Predicate(self.0.clone())
+    }
+}
+
+impl<E: 'static> Predicate<E> {
+    pub fn new<F: Fn(&E) -> bool + Send + Sync + 'static>(f: F) -> Predicate<E> {
+        Predicate(Arc::new(f))
+    }
+
+    pub fn test(&self, event: &E) -> bool {
+        (self.0)(event)
+    }
+
+    /// 两个条件都满足
+    pub fn and(self, other: Predicate<E>) -> Predicate<E> {
+        Predicate::new(move |event| self.test(event) && other.test(event))
+    }
+
+    /// 满足任意一个条件
+    pub fn or(self, other: Predicate<E>) -> Predicate<E> {
+        Predicate::new(move |event| self.test(event) || other.test(event))
+    }
+}
+
+impl<E: 'static> Not for Predicate<E> {
+    type Output = Predicate<E>;
+
+    fn not(self) -> Predicate<E> {
+        Predicate::new(move |event| !self.test(event))
+    }
+}
+
+/// 事件来自这些群，私聊和好友事件不满足
+pub fn in_groups<E: Event, I: IntoIterator<Item = i64>>(group_ids: I) -> Predicate<E> {
+    let group_ids: HashSet<i64> = group_ids.into_iter().collect();
+    Predicate::new(move |event: &E| event.group_id().map(|group_id| group_ids.contains(&group_id)).unwrap_or(false))
+}
+
+/// 事件由这些用户触发
+pub fn from_users<E: Event, I: IntoIterator<Item = i64>>(user_ids: I) -> Predicate<E> {
+    let user_ids: HashSet<i64> = user_ids.into_iter().collect();
+    Predicate::new(move |event: &E| user_ids.contains(&event.user_id()))
+}
+
+/// 群消息发送者的角色，owner、admin 或 member
+pub fn role(role: &str) -> Predicate<GroupMessageEvent> {
+    let role = role.to_string();
+    Predicate::new(move |event: &GroupMessageEvent| event.sender.as_ref().map(|sender| sender.role == role).unwrap_or(false))
+}
+
+/// 纯文本以 prefix 开头
+pub fn prefix<E: HasMessage>(prefix: &str) -> Predicate<E> {
+    let prefix = prefix.to_string();
+    Predicate::new(move |event: &E| plain_text(event.message()).starts_with(&prefix))
+}
+
+///
+/// 纯文本匹配正则表达式
+///
+/// @param pattern 正则表达式，不合法时 panic
+///
+pub fn regex<E: HasMessage>(pattern: &str) -> Predicate<E> {
+    let regex = Regex::new(pattern).expect("invalid regex");
+    Predicate::new(move |event: &E| regex.is_match(&plain_text(event.message())))
+}
+
+/// 事件的 sub_type，例如群成员减少的 leave、kick
+pub fn sub_type<E: HasSubType>(sub_type: &str) -> Predicate<E> {
+    let sub_type = sub_type.to_string();
+    Predicate::new(move |event: &E| event.sub_type() == sub_type)
+}
+
+/// 路由中正则表达式的捕获组，没有使用 regex 时为空
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Captures(Vec<Option<String>>);
+
+impl Captures {
+    /// 第 index 个捕获组，0 是整个匹配
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.0.get(index)?.as_deref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+///
+/// 路由处理函数，可以直接使用 async 闭包 |ctx, captures| async move { ... }
+///
+/// 闭包返回 () 时继续传播，也可以返回 Propagation
+///
+#[async_trait]
+pub trait RouteHandler<E>: Send + Sync {
+    async fn handle(&self, ctx: EventContext<E>, captures: Captures) -> Propagation;
+}
+
+#[async_trait]
+impl<E, F, Fut, R> RouteHandler<E> for F
+where
+    E: Event,
+    F: Fn(EventContext<E>, Captures) -> Fut + Send + Sync,
+    Fut: Future<Output = R> + Send + 'static,
+    R: Into<Propagation>,
+{
+    async fn handle(&self, ctx: EventContext<E>, captures: Captures) -> Propagation {
+        self(ctx, captures).await.into()
+    }
+}
+
+/// 从事件中取出捕获组，不匹配时返回 None
+type CaptureFn<E> = Arc<dyn Fn(&E) -> Option<Captures> + Send + Sync>;
+
+struct Route<E> {
+    priority: i32,
+    predicates: Vec<Predicate<E>>,
+    captures: Option<CaptureFn<E>>,
+    handler: Arc<dyn RouteHandler<E>>,
+}
+
+impl<E: Event> Route<E> {
+    /// 满足所有条件时返回捕获组
+    fn matches(&self, event: &E) -> Option<Captures> {
+        if !self.predicates.iter().all(|predicate| predicate.test(event)) {
+            return None;
+        }
+        match &self.captures {
+            Some(captures) => captures(event),
+            None => Some(Captures::default()),
+        }
+    }
+}
+
+///
+/// 路由构造器，添加条件后调用 handle 注册
+///
+pub struct RouteBuilder<'a, E> {
+    routes: &'a RwLock<Vec<Route<E>>>,
+    priority: i32,
+    predicates: Vec<Predicate<E>>,
+    captures: Option<CaptureFn<E>>,
+}
+
+impl<'a, E: Event> RouteBuilder<'a, E> {
+    fn new(routes: &'a RwLock<Vec<Route<E>>>) -> RouteBuilder<'a, E> {
+        RouteBuilder { routes, priority: 0, predicates: Vec::new(), captures: None }
+    }
+
+    /// 优先级，越大越先执行，默认 0，相同时按注册顺序
+    pub fn priority(mut self, priority: i32) -> RouteBuilder<'a, E> {
+        self.priority = priority;
+        self
+    }
+
+    /// 添加条件，所有条件都满足时才处理
+    pub fn when(mut self, predicate: Predicate<E>) -> RouteBuilder<'a, E> {
+        self.predicates.push(predicate);
+        self
+    }
+
+    /// 用闭包作为条件
+    pub fn filter<F: Fn(&E) -> bool + Send + Sync + 'static>(self, f: F) -> RouteBuilder<'a, E> {
+        self.when(Predicate::new(f))
+    }
+
+    pub fn in_groups<I: IntoIterator<Item = i64>>(self, group_ids: I) -> RouteBuilder<'a, E> {
+        self.when(in_groups(group_ids))
+    }
+
+    pub fn from_users<I: IntoIterator<Item = i64>>(self, user_ids: I) -> RouteBuilder<'a, E> {
+        self.when(from_users(user_ids))
+    }
+
+    ///
+    /// 注册处理函数
+    ///
+    /// @param handler async |ctx, captures| { ... }
+    ///
+    pub fn handle<H: RouteHandler<E> + 'static>(self, handler: H) {
+        let mut routes = self.routes.write().unwrap();
+        let index = routes.iter().position(|route| route.priority < self.priority).unwrap_or(routes.len());
+        routes.insert(index, Route {
+            priority: self.priority,
+            predicates: self.predicates,
+            captures: self.captures,
+            handler: Arc::new(handler),
+        });
+    }
+}
+
+impl<'a, E: HasMessage> RouteBuilder<'a, E> {
+    pub fn prefix(self, text: &str) -> RouteBuilder<'a, E> {
+        self.when(prefix(text))
+    }
+
+    ///
+    /// 纯文本匹配正则表达式，捕获组传给处理函数
+    ///
+    /// 每个路由只能调用一次，其他正则条件使用 when(regex(..))
+    ///
+    /// @param pattern 正则表达式，不合法或重复调用时 panic
+    ///
+    pub fn regex(mut self, pattern: &str) -> RouteBuilder<'a, E> {
+        assert!(self.captures.is_none(), "每个路由只能调用一次 regex");
+        let regex = Regex::new(pattern).expect("invalid regex");
+        self.captures = Some(Arc::new(move |event: &E| {
+            let text = plain_text(event.message());
+            let captures = regex.captures(&text)?;
+            Some(Captures(captures.iter().map(|group| group.map(|group| group.as_str().to_string())).collect()))
+        }));
+        self
+    }
+}
+
+impl<'a, E: HasSubType> RouteBuilder<'a, E> {
+    pub fn sub_type(self, value: &str) -> RouteBuilder<'a, E> {
+        self.when(sub_type(value))
+    }
+}
+
+impl<'a> RouteBuilder<'a, GroupMessageEvent> {
+    pub fn role(self, value: &str) -> RouteBuilder<'a, GroupMessageEvent> {
+        self.when(role(value))
+    }
+}
+
+async fn run_routes<E: Event>(routes: &RwLock<Vec<Route<E>>>, ctx: &EventContext<E>) -> Propagation {
+    // 条件是同步的，先在锁内选出匹配的路由，再逐个执行
+    let matched: Vec<(Arc<dyn RouteHandler<E>>, Captures)> = routes.read().unwrap().iter()
+        .filter_map(|route| route.matches(&ctx.event).map(|captures| (route.handler.clone(), captures)))
+        .collect();
+    for (handler, captures) in matched {
+        if handler.handle(ctx.clone(), captures).await == Propagation::Stop {
+            return Propagation::Stop;
+        }
+    }
+    Propagation::Continue
+}
+
+macro_rules! router {
+    ($($(#[$doc:meta])* $method:ident => $field:ident: $event:ty;)*) => {
+        #[derive(Default)]
+        struct Routes {
+            $($field: RwLock<Vec<Route<$event>>>,)*
+        }
+
+        ///
+        /// 声明式事件路由，作为 EventHandler 注册
+        ///
+        /// 满足条件的路由按优先级从高到低执行，优先级相同时按注册顺序，
+        /// 某个路由返回 Propagation::Stop 后，后面的路由和 EventHandler 都不再执行
+        ///
+        /// ```
+        /// use rs_pbbot_demo::context::EventContext;
+        /// use rs_pbbot_demo::msg::text;
+        /// use rs_pbbot_demo::onebot::GroupMessageEvent;
+        /// use rs_pbbot_demo::router::Captures;
+        /// use rs_pbbot_demo::{BotBuilder, BotServer, Router};
+        ///
+        /// let router = Router::new();
+        /// router.on_group_message()
+        ///     .in_groups([123456])
+        ///     .regex("^roll (\\d+)")
+        ///     .handle(|ctx: EventContext<GroupMessageEvent>, captures: Captures| async move {
+        ///         let _ = ctx.reply(text(captures.get(1).unwrap())).await;
+        ///     });
+        /// let server = BotServer::new().handler(router);
+        /// ```
+        ///
+        /// 处理函数的参数需要标注类型，闭包的参数类型无法从 RouteHandler 推断
+        ///
+        #[derive(Clone, Default)]
+        pub struct Router {
+            routes: Arc<Routes>,
+        }
+
+        impl Router {
+            pub fn new() -> Router {
+                Default::default()
+            }
+
+            $($(#[$doc])*
+            pub fn $method(&self) -> RouteBuilder<'_, $event> {
+                RouteBuilder::new(&self.routes.$field)
+            })*
+        }
+
+        #[async_trait]
+        impl EventHandler for Router {
+            $(async fn $method(&self, ctx: &EventContext<$event>) -> Propagation {
+                run_routes(&self.routes.$field, ctx).await
+            })*
+        }
+    };
+}
+
+router! {
+    /// 私聊消息
+    on_private_message => private_message: PrivateMessageEvent;
+    /// 群消息
+    on_group_message => group_message: GroupMessageEvent;
+    /// 群文件上传
+    on_group_upload_notice => group_upload_notice: GroupUploadNoticeEvent;
+    /// 群管理员变动
+    on_group_admin_notice => group_admin_notice: GroupAdminNoticeEvent;
+    /// 群成员减少
+    on_group_decrease_notice => group_decrease_notice: GroupDecreaseNoticeEvent;
+    /// 群成员增加
+    on_group_increase_notice => group_increase_notice: GroupIncreaseNoticeEvent;
+    /// 群禁言
+    on_group_ban_notice => group_ban_notice: GroupBanNoticeEvent;
+    /// 好友添加
+    on_friend_add_notice => friend_add_notice: FriendAddNoticeEvent;
+    /// 群消息撤回
+    on_group_recall_notice => group_recall_notice: GroupRecallNoticeEvent;
+    /// 好友消息撤回
+    on_friend_recall_notice => friend_recall_notice: FriendRecallNoticeEvent;
+    /// 加好友请求
+    on_friend_request => friend_request: FriendRequestEvent;
+    /// 加群请求／邀请
+    on_group_request => group_request: GroupRequestEvent;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::Bot;
+    use crate::dispatcher::Dispatcher;
+    use crate::msg::text;
+    use crate::onebot::frame::Data;
+    use std::sync::Mutex;
+    use tokio::sync::mpsc;
+
+    fn group_message(group_id: i64, user_id: i64, role: &str, content: &str) -> Data {
+        Data::GroupMessageEvent(GroupMessageEvent {
+            group_id,
+            user_id,
+            message: vec![text(content)],
+            sender: Some(group_message_event::Sender { role: role.to_string(), ..Default::default() }),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn routes_match_predicates_and_capture() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let router = Router::new();
+        router.on_group_message().in_groups([100]).regex("^roll (\\d+)$").handle({
+            let log = log.clone();
+            move |ctx: EventContext<GroupMessageEvent>, captures: Captures| {
+                log.lock().unwrap().push(format!("roll {} {}", ctx.user_id, captures.get(1).unwrap()));
+                async {}
+            }
+        });
+        router.on_group_message().prefix("!").when(role("admin").or(role("owner"))).handle({
+            let log = log.clone();
+            move |ctx: EventContext<GroupMessageEvent>, _| {
+                log.lock().unwrap().push(format!("admin {}", ctx.user_id));
+                async {}
+            }
+        });
+        router.on_group_decrease_notice().sub_type("kick").when(!from_users([3])).handle({
+            let log = log.clone();
+            move |ctx: EventContext<GroupDecreaseNoticeEvent>, _| {
+                log.lock().unwrap().push(format!("kick {}", ctx.user_id));
+                async {}
+            }
+        });
+
+        let (api_sender, _api_receiver) = mpsc::channel(10);
+        let bot = Bot::new(10001, api_sender);
+        let dispatcher = Dispatcher::new().add_handler(router);
+        let events = [
+            group_message(100, 1, "member", "roll 20"),
+            group_message(200, 1, "member", "roll 20"),
+            group_message(100, 1, "member", "roll x"),
+            group_message(100, 2, "member", "!ban"),
+            group_message(100, 2, "owner", "!ban"),
+            Data::GroupDecreaseNoticeEvent(GroupDecreaseNoticeEvent { sub_type: "leave".to_string(), user_id: 4, ..Default::default() }),
+            Data::GroupDecreaseNoticeEvent(GroupDecreaseNoticeEvent { sub_type: "kick".to_string(), user_id: 3, ..Default::default() }),
+            Data::GroupDecreaseNoticeEvent(GroupDecreaseNoticeEvent { sub_type: "kick".to_string(), user_id: 4, ..Default::default() }),
+        ];
+        for event in &events {
+            dispatcher.dispatch(bot.clone(), event).await;
+        }
+
+        assert_eq!(*log.lock().unwrap(), vec!["roll 1 20", "admin 2", "kick 4"]);
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl EventHandler for Recorder {
+        async fn on_group_message(&self, ctx: &EventContext<GroupMessageEvent>) -> Propagation {
+            self.0.lock().unwrap().push(format!("handler {}", ctx.user_id));
+            Propagation::Continue
+        }
+    }
+
+    #[tokio::test]
+    async fn unmatched_routes_do_not_run() {
+        let log = Recorder::default();
+        let router = Router::new();
+        router.on_group_message()
+            .from_users([1])
+            .filter(|event| event.message.len() == 1)
+            .when(!prefix("#"))
+            .handle({
+                let log = log.clone();
+                move |ctx: EventContext<GroupMessageEvent>, _| {
+                    log.0.lock().unwrap().push(format!("route {}", ctx.user_id));
+                    async { Propagation::Stop }
+                }
+            });
+
+        let (api_sender, _api_receiver) = mpsc::channel(10);
+        let bot = Bot::new(10001, api_sender);
+        let dispatcher = Dispatcher::new().add_handler_with_priority(router, 1).add_handler(log.clone());
+        dispatcher.dispatch(bot.clone(), &group_message(100, 2, "member", "hi")).await;
+        dispatcher.dispatch(bot.clone(), &group_message(100, 1, "member", "#hi")).await;
+        dispatcher.dispatch(bot.clone(), &Data::GroupMessageEvent(GroupMessageEvent {
+            user_id: 1,
+            message: vec![text("a"), text("b")],
+            ..Default::default()
+        })).await;
+        dispatcher.dispatch(bot.clone(), &Data::PrivateMessageEvent(PrivateMessageEvent { user_id: 1, ..Default::default() })).await;
+        // 满足所有条件的路由返回 Stop，后面的处理器不再执行
+        dispatcher.dispatch(bot.clone(), &group_message(100, 1, "member", "hi")).await;
+
+        assert_eq!(*log.0.lock().unwrap(), vec!["handler 2", "handler 1", "handler 1", "route 1"]);
+    }
+
+    #[tokio::test]
+    async fn regex_passes_capture_groups() {
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let router = Router::new();
+        router.on_private_message().regex("^roll (\\d+)(?:d(\\d+))?$").handle({
+            let captured = captured.clone();
+            move |_: EventContext<PrivateMessageEvent>, captures: Captures| {
+                captured.lock().unwrap().push(captures);
+                async {}
+            }
+        });
+
+        let (api_sender, _api_receiver) = mpsc::channel(10);
+        let bot = Bot::new(10001, api_sender);
+        let dispatcher = Dispatcher::new().add_handler(router);
+        for content in ["roll 2d6", "roll 20", "roll x"] {
+            dispatcher.dispatch(bot.clone(), &Data::PrivateMessageEvent(PrivateMessageEvent {
+                message: vec![text(content)],
+                ..Default::default()
+            })).await;
+        }
+
+        let captured = captured.lock().unwrap();
+        assert_eq!(captured.len(), 2);
+        assert_eq!((captured[0].get(0), captured[0].get(1), captured[0].get(2)), (Some("roll 2d6"), Some("2"), Some("6")));
+        assert_eq!(captured[1].len(), 3);
+        assert_eq!((captured[1].get(1), captured[1].get(2), captured[1].get(3)), (Some("20"), None, None));
+    }
+
+    #[test]
+    #[should_panic(expected = "regex")]
+    fn regex_twice_panics() {
+        let router = Router::new();
+        let _ = router.on_group_message().regex("^a").regex("^b");
+    }
+}
@@ -0,0 +1,436 @@
+use crate::onebot::Message;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+///
+/// 类型化的消息段，和 onebot::Message 互相转换不丢失信息
+///
+/// 未知类型，或者包含未知 key 的消息段转换为 Unknown，原样保留
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Text { text: String },
+    Face { id: i32 },
+    /// image_type 为 flash（闪照）或 show（秀图），秀图时 effect_id 为特效 ID
+    Image { file: Option<String>, url: Option<String>, image_type: Option<String>, effect_id: Option<i32> },
+    Record { file: Option<String>, url: Option<String> },
+    Video { file: Option<String>, url: Option<String>, cover: Option<String>, cache: Option<bool> },
+    At { qq: i64 },
+    AtAll,
+    Poke { qq: i64 },
+    Share { url: String, title: String, content: Option<String>, image: Option<String> },
+    LightApp { content: String },
+    /// sub_type 为 xml 或 json
+    Service { sub_type: String, id: i32, content: String },
+    Reply { message_id: i32 },
+    Sleep { time: i64 },
+    Tts { text: String },
+    Gift { qq: i64, id: i32 },
+    Unknown(Message),
+}
+
+/// 已知类型的消息段缺少必需的 key，或者 value 无法解析
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SegmentError {
+    MissingKey { r#type: String, key: String },
+    InvalidValue { r#type: String, key: String, value: String },
+    UnknownType { r#type: String },
+    UnknownKey { r#type: String, key: String },
+}
+
+impl fmt::Display for SegmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SegmentError::MissingKey { r#type, key } => write!(f, "{} segment missing key {}", r#type, key),
+            SegmentError::InvalidValue { r#type, key, value } => write!(f, "{} segment has invalid {}: {:?}", r#type, key, value),
+            SegmentError::UnknownType { r#type } => write!(f, "unknown segment type {}", r#type),
+            SegmentError::UnknownKey { r#type, key } => write!(f, "{} segment has unknown key {}", r#type, key),
+        }
+    }
+}
+
+impl std::error::Error for SegmentError {}
+
+///
+/// 消息段的 key，(必需, 可选)，参考 OneBot 消息段规范和 Go-Mirai-Client 的扩展
+///
+fn segment_keys(r#type: &str) -> Option<(&'static [&'static str], &'static [&'static str])> {
+    let keys: (&[&str], &[&str]) = match r#type {
+        "text" => (&["text"], &[]),
+        "face" => (&["id"], &[]),
+        "image" => (&[], &["file", "url", "type", "effect_id", "cache", "proxy", "timeout"]),
+        "record" => (&[], &["file", "url", "magic", "cache", "proxy", "timeout"]),
+        "video" => (&[], &["file", "url", "cover", "cache", "proxy", "timeout"]),
+        "at" => (&["qq"], &[]),
+        "rps" | "dice" | "shake" => (&[], &[]),
+        "anonymous" => (&[], &["ignore"]),
+        "contact" => (&["type", "id"], &[]),
+        "location" => (&["lat", "lon"], &["title", "content"]),
+        // type 为 qq、163、xm 时需要 id，为 custom 时需要 url、audio、title
+        "music" => (&["type"], &["id", "url", "audio", "title", "content", "image"]),
+        "forward" => (&["id"], &[]),
+        // 引用已有消息时使用 id，自定义节点使用 user_id、nickname、content
+        "node" => (&[], &["id", "user_id", "nickname", "content"]),
+        "xml" | "json" => (&["data"], &[]),
+        "poke" => (&["qq"], &[]),
+        "share" => (&["url", "title"], &["content", "image"]),
+        "light_app" => (&["content"], &[]),
+        "service" => (&["sub_type", "id", "content"], &[]),
+        "reply" => (&["message_id"], &[]),
+        "sleep" => (&["time"], &[]),
+        "tts" => (&["text"], &[]),
+        "gift" => (&["qq", "id"], &[]),
+        _ => return None,
+    };
+    Some(keys)
+}
+
+///
+/// 按消息段规范检查消息，报告未知类型、缺少的 key、未知的 key 和无法解析的值
+///
+/// @param message 消息段
+/// @return 所有问题，没有问题时返回 Ok
+///
+pub fn validate(message: &Message) -> Result<(), Vec<SegmentError>> {
+    let (required, optional) = match segment_keys(&message.r#type) {
+        Some(keys) => keys,
+        None => return Err(vec![SegmentError::UnknownType { r#type: message.r#type.clone() }]),
+    };
+    let mut errors: Vec<SegmentError> = required.iter()
+        .filter(|key| !message.data.contains_key(**key))
+        .map(|key| SegmentError::MissingKey { r#type: message.r#type.clone(), key: key.to_string() })
+        .collect();
+    let mut unknown_keys: Vec<&String> = message.data.keys()
+        .filter(|key| !required.contains(&key.as_str()) && !optional.contains(&key.as_str()))
+        .collect();
+    unknown_keys.sort();
+    errors.extend(unknown_keys.into_iter()
+        .map(|key| SegmentError::UnknownKey { r#type: message.r#type.clone(), key: key.clone() }));
+    if errors.is_empty() {
+        if let Err(err) = Segment::try_from(message) {
+            errors.push(err);
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// 按 key 读取消息段数据，记录读过的 key 数量，用于判断是否有未知 key
+struct Fields<'a> {
+    message: &'a Message,
+    used: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn optional(&mut self, key: &str) -> Option<String> {
+        let value = self.message.data.get(key)?;
+        self.used += 1;
+        Some(value.clone())
+    }
+
+    fn required(&mut self, key: &str) -> Result<String, SegmentError> {
+        self.optional(key).ok_or_else(|| SegmentError::MissingKey {
+            r#type: self.message.r#type.clone(),
+            key: key.to_string(),
+        })
+    }
+
+    /// 只接受能原样转换回字符串的值，保证转换不丢失信息
+    fn parse<T: FromStr + ToString>(&self, key: &str, value: String) -> Result<T, SegmentError> {
+        match value.parse::<T>() {
+            Ok(parsed) if parsed.to_string() == value => Ok(parsed),
+            _ => Err(SegmentError::InvalidValue {
+                r#type: self.message.r#type.clone(),
+                key: key.to_string(),
+                value,
+            }),
+        }
+    }
+
+    fn required_parse<T: FromStr + ToString>(&mut self, key: &str) -> Result<T, SegmentError> {
+        let value = self.required(key)?;
+        self.parse(key, value)
+    }
+
+    fn optional_parse<T: FromStr + ToString>(&mut self, key: &str) -> Result<Option<T>, SegmentError> {
+        match self.optional(key) {
+            Some(value) => self.parse(key, value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn optional_bool(&mut self, key: &str) -> Result<Option<bool>, SegmentError> {
+        match self.optional(key).as_deref() {
+            Some("1") => Ok(Some(true)),
+            Some("0") => Ok(Some(false)),
+            Some(value) => Err(SegmentError::InvalidValue {
+                r#type: self.message.r#type.clone(),
+                key: key.to_string(),
+                value: value.to_string(),
+            }),
+            None => Ok(None),
+        }
+    }
+}
+
+impl TryFrom<&Message> for Segment {
+    type Error = SegmentError;
+
+    fn try_from(message: &Message) -> Result<Self, Self::Error> {
+        let mut fields = Fields { message, used: 0 };
+        let segment = match message.r#type.as_str() {
+            "text" => Segment::Text { text: fields.required("text")? },
+            "face" => Segment::Face { id: fields.required_parse("id")? },
+            "image" => Segment::Image {
+                file: fields.optional("file"),
+                url: fields.optional("url"),
+                image_type: fields.optional("type"),
+                effect_id: fields.optional_parse("effect_id")?,
+            },
+            "record" => Segment::Record {
+                file: fields.optional("file"),
+                url: fields.optional("url"),
+            },
+            "video" => Segment::Video {
+                file: fields.optional("file"),
+                url: fields.optional("url"),
+                cover: fields.optional("cover"),
+                cache: fields.optional_bool("cache")?,
+            },
+            "at" => match fields.required("qq")?.as_str() {
+                "all" => Segment::AtAll,
+                qq => Segment::At { qq: fields.parse("qq", qq.to_string())? },
+            },
+            "poke" => Segment::Poke { qq: fields.required_parse("qq")? },
+            "share" => Segment::Share {
+                url: fields.required("url")?,
+                title: fields.required("title")?,
+                content: fields.optional("content"),
+                image: fields.optional("image"),
+            },
+            "light_app" => Segment::LightApp { content: fields.required("content")? },
+            "service" => Segment::Service {
+                sub_type: fields.required("sub_type")?,
+                id: fields.required_parse("id")?,
+                content: fields.required("content")?,
+            },
+            "reply" => Segment::Reply { message_id: fields.required_parse("message_id")? },
+            "sleep" => Segment::Sleep { time: fields.required_parse("time")? },
+            "tts" => Segment::Tts { text: fields.required("text")? },
+            "gift" => Segment::Gift {
+                qq: fields.required_parse("qq")?,
+                id: fields.required_parse("id")?,
+            },
+            _ => return Ok(Segment::Unknown(message.clone())),
+        };
+        if fields.used != message.data.len() {
+            // 有未知 key，保留原始消息段
+            return Ok(Segment::Unknown(message.clone()));
+        }
+        Ok(segment)
+    }
+}
+
+impl TryFrom<Message> for Segment {
+    type Error = SegmentError;
+
+    fn try_from(message: Message) -> Result<Self, Self::Error> {
+        Segment::try_from(&message)
+    }
+}
+
+impl From<Segment> for Message {
+    fn from(segment: Segment) -> Self {
+        let (r#type, fields): (&str, Vec<(&str, Option<String>)>) = match segment {
+            Segment::Text { text } => ("text", vec![("text", Some(text))]),
+            Segment::Face { id } => ("face", vec![("id", Some(id.to_string()))]),
+            Segment::Image { file, url, image_type, effect_id } => ("image", vec![
+                ("file", file),
+                ("url", url),
+                ("type", image_type),
+                ("effect_id", effect_id.map(|effect_id| effect_id.to_string())),
+            ]),
+            Segment::Record { file, url } => ("record", vec![("file", file), ("url", url)]),
+            Segment::Video { file, url, cover, cache } => ("video", vec![
+                ("file", file),
+                ("url", url),
+                ("cover", cover),
+                ("cache", cache.map(|cache| if cache { "1".to_string() } else { "0".to_string() })),
+            ]),
+            Segment::At { qq } => ("at", vec![("qq", Some(qq.to_string()))]),
+            Segment::AtAll => ("at", vec![("qq", Some("all".to_string()))]),
+            Segment::Poke { qq } => ("poke", vec![("qq", Some(qq.to_string()))]),
+            Segment::Share { url, title, content, image } => ("share", vec![
+                ("url", Some(url)),
+                ("title", Some(title)),
+                ("content", content),
+                ("image", image),
+            ]),
+            Segment::LightApp { content } => ("light_app", vec![("content", Some(content))]),
+            Segment::Service { sub_type, id, content } => ("service", vec![
+                ("sub_type", Some(sub_type)),
+                ("id", Some(id.to_string())),
+                ("content", Some(content)),
+            ]),
+            Segment::Reply { message_id } => ("reply", vec![("message_id", Some(message_id.to_string()))]),
+            Segment::Sleep { time } => ("sleep", vec![("time", Some(time.to_string()))]),
+            Segment::Tts { text } => ("tts", vec![("text", Some(text))]),
+            Segment::Gift { qq, id } => ("gift", vec![("qq", Some(qq.to_string())), ("id", Some(id.to_string()))]),
+            Segment::Unknown(message) => return message,
+        };
+        Message {
+            r#type: r#type.to_string(),
+            data: fields.into_iter()
+                .filter_map(|(key, value)| value.map(|value| (key.to_string(), value)))
+                .collect(),
+        }
+    }
+}
+
+impl From<Segment> for Vec<Message> {
+    fn from(segment: Segment) -> Self {
+        vec![segment.into()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg;
+    use std::collections::HashMap;
+
+    fn round_trip(message: Message) -> Segment {
+        let segment = Segment::try_from(&message).unwrap();
+        assert_eq!(Message::from(segment.clone()), message);
+        segment
+    }
+
+    #[test]
+    fn builders_round_trip() {
+        assert_eq!(round_trip(msg::text("hello")), Segment::Text { text: "hello".to_string() });
+        assert_eq!(round_trip(msg::image("http://a/b.png")), Segment::Image {
+            file: None,
+            url: Some("http://a/b.png".to_string()),
+            image_type: None,
+            effect_id: None,
+        });
+        assert_eq!(round_trip(msg::record("http://a/b.amr")), Segment::Record { file: None, url: Some("http://a/b.amr".to_string()) });
+        assert_eq!(round_trip(msg::flash("http://a/b.png")), Segment::Image {
+            file: None,
+            url: Some("http://a/b.png".to_string()),
+            image_type: Some("flash".to_string()),
+            effect_id: None,
+        });
+        assert_eq!(round_trip(msg::show("http://a/b.png", 40001)), Segment::Image {
+            file: None,
+            url: Some("http://a/b.png".to_string()),
+            image_type: Some("show".to_string()),
+            effect_id: Some(40001),
+        });
+        assert_eq!(round_trip(msg::at(123456)), Segment::At { qq: 123456 });
+        assert_eq!(round_trip(msg::at_all()), Segment::AtAll);
+        assert_eq!(round_trip(msg::face(1)), Segment::Face { id: 1 });
+        assert_eq!(round_trip(msg::poke(123456)), Segment::Poke { qq: 123456 });
+        assert_eq!(round_trip(msg::share("http://a", "title", "content", "http://a/b.png")), Segment::Share {
+            url: "http://a".to_string(),
+            title: "title".to_string(),
+            content: Some("content".to_string()),
+            image: Some("http://a/b.png".to_string()),
+        });
+        assert_eq!(round_trip(msg::light_app("{}")), Segment::LightApp { content: "{}".to_string() });
+        assert_eq!(round_trip(msg::xml(1, "<xml/>")), Segment::Service { sub_type: "xml".to_string(), id: 1, content: "<xml/>".to_string() });
+        assert_eq!(round_trip(msg::json(1, "{}")), Segment::Service { sub_type: "json".to_string(), id: 1, content: "{}".to_string() });
+        assert_eq!(round_trip(msg::reply(42)), Segment::Reply { message_id: 42 });
+        assert_eq!(round_trip(msg::sleep(1000)), Segment::Sleep { time: 1000 });
+        assert_eq!(round_trip(msg::tts("hello")), Segment::Tts { text: "hello".to_string() });
+        assert_eq!(round_trip(msg::video("http://a/b.mp4", "http://a/b.png", true)), Segment::Video {
+            file: None,
+            url: Some("http://a/b.mp4".to_string()),
+            cover: Some("http://a/b.png".to_string()),
+            cache: Some(true),
+        });
+        assert_eq!(round_trip(msg::gift(123456, 1)), Segment::Gift { qq: 123456, id: 1 });
+    }
+
+    #[test]
+    fn builders_are_valid() {
+        let messages = vec![
+            msg::text("hello"),
+            msg::image("http://a/b.png"),
+            msg::record("http://a/b.amr"),
+            msg::flash("http://a/b.png"),
+            msg::show("http://a/b.png", 40001),
+            msg::at(123456),
+            msg::at_all(),
+            msg::face(1),
+            msg::poke(123456),
+            msg::share("http://a", "title", "content", "http://a/b.png"),
+            msg::light_app("{}"),
+            msg::xml(1, "<xml/>"),
+            msg::json(1, "{}"),
+            msg::reply(42),
+            msg::sleep(1000),
+            msg::tts("hello"),
+            msg::video("http://a/b.mp4", "http://a/b.png", true),
+            msg::gift(123456, 1),
+        ];
+        for message in &messages {
+            assert_eq!(validate(message), Ok(()), "{:?}", message);
+        }
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let unknown_type = Message { r#type: "foo".to_string(), data: HashMap::new() };
+        assert_eq!(validate(&unknown_type), Err(vec![SegmentError::UnknownType { r#type: "foo".to_string() }]));
+
+        let mut wrong_keys = msg::gift(123456, 1);
+        wrong_keys.data.remove("qq");
+        wrong_keys.data.insert("at".to_string(), "123456".to_string());
+        wrong_keys.data.insert("b".to_string(), "".to_string());
+        assert_eq!(validate(&wrong_keys), Err(vec![
+            SegmentError::MissingKey { r#type: "gift".to_string(), key: "qq".to_string() },
+            SegmentError::UnknownKey { r#type: "gift".to_string(), key: "at".to_string() },
+            SegmentError::UnknownKey { r#type: "gift".to_string(), key: "b".to_string() },
+        ]));
+
+        let mut invalid = msg::at(1);
+        invalid.data.insert("qq".to_string(), "someone".to_string());
+        assert_eq!(validate(&invalid), Err(vec![SegmentError::InvalidValue {
+            r#type: "at".to_string(),
+            key: "qq".to_string(),
+            value: "someone".to_string(),
+        }]));
+    }
+
+    #[test]
+    fn unknown_type_and_keys_are_kept() {
+        let unknown_type = Message { r#type: "dice".to_string(), data: HashMap::new() };
+        assert_eq!(round_trip(unknown_type.clone()), Segment::Unknown(unknown_type));
+
+        let mut extra_key = msg::text("hello");
+        extra_key.data.insert("color".to_string(), "red".to_string());
+        assert_eq!(round_trip(extra_key.clone()), Segment::Unknown(extra_key));
+    }
+
+    #[test]
+    fn invalid_known_segments() {
+        let missing = Message { r#type: "text".to_string(), data: HashMap::new() };
+        assert_eq!(Segment::try_from(&missing), Err(SegmentError::MissingKey {
+            r#type: "text".to_string(),
+            key: "text".to_string(),
+        }));
+
+        let mut invalid = msg::face(1);
+        invalid.data.insert("id".to_string(), "01".to_string());
+        assert_eq!(Segment::try_from(&invalid), Err(SegmentError::InvalidValue {
+            r#type: "face".to_string(),
+            key: "id".to_string(),
+            value: "01".to_string(),
+        }));
+    }
+}
@@ -0,0 +1,488 @@
+use crate::auth::Auth;
+use crate::codec::Codec;
+use crate::connection::{self, ConnectionConfig};
+use crate::options::{BotBuilder, BotOptions};
+use crate::shutdown::{self, InFlight};
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::{Extension, Query};
+use axum::handler::get;
+use axum::http::header::HeaderMap;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{AddExtensionLayer, Router};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tracing::Instrument;
+
+pub use crate::connection::DEFAULT_MAX_DECODE_ERRORS;
+
+///
+/// 反向 websocket 服务器，Go-Mirai-Client 连接到这里
+///
+/// 事件处理器、中间件和退出等配置通过 BotBuilder 设置，和 BotClient 相同
+///
+/// BotServer::new()
+///     .bind(([127, 0, 0, 1], 8081))
+///     .path("/ws/cq/")
+///     .route("/ws/json/", Codec::Json)
+///     .detect_route("/ws/auto/")
+///     .handler(MyHandler)
+///     .run()
+///     .await
+///
+pub struct BotServer {
+    addr: SocketAddr,
+    path: String,
+    /// 额外的路径和编码，None 表示由收到的第一个 Frame 决定
+    routes: Vec<(String, Option<Codec>)>,
+    auth: Auth,
+    options: BotOptions,
+}
+
+/// 所有连接共享的状态
+#[derive(Clone)]
+struct ServerState {
+    connection: ConnectionConfig,
+    auth: Auth,
+    /// 服务器退出前等待所有连接关闭
+    connections: InFlight,
+}
+
+impl Default for BotServer {
+    fn default() -> Self {
+        BotServer {
+            addr: SocketAddr::from(([127, 0, 0, 1], 8081)),
+            path: "/ws/cq/".to_string(),
+            routes: Vec::new(),
+            auth: Auth::new(),
+            options: BotOptions::default(),
+        }
+    }
+}
+
+impl BotServer {
+    pub fn new() -> BotServer {
+        Default::default()
+    }
+
+    /// 监听地址，默认 127.0.0.1:8081
+    pub fn bind<A: Into<SocketAddr>>(mut self, addr: A) -> BotServer {
+        self.addr = addr.into();
+        self
+    }
+
+    /// websocket 路径，默认 /ws/cq/，使用 Protobuf 编码
+    pub fn path(mut self, path: &str) -> BotServer {
+        self.path = path.to_string();
+        self
+    }
+
+    ///
+    /// 添加使用固定编码的 websocket 路径
+    ///
+    /// 连接上和编码不一致的消息会被忽略，API 请求从连接开始就使用这个编码
+    ///
+    /// @param path  websocket 路径
+    /// @param codec Protobuf 使用 Binary 消息，Json 使用 Text 消息
+    ///
+    pub fn route(mut self, path: &str, codec: Codec) -> BotServer {
+        self.routes.push((path.to_string(), Some(codec)));
+        self
+    }
+
+    ///
+    /// 添加由收到的第一个 Frame 决定编码的 websocket 路径
+    ///
+    /// 编码确定前 API 请求留在队列中不会发送，在 on_bot_connected 中调用 API 会等到对端发送第一个 Frame，
+    /// 对端不主动发送 Frame 时会超时
+    ///
+    pub fn detect_route(mut self, path: &str) -> BotServer {
+        self.routes.push((path.to_string(), None));
+        self
+    }
+
+    /// 连接鉴权，默认只要求 x-self-id 合法
+    pub fn auth(mut self, auth: Auth) -> BotServer {
+        self.auth = auth;
+        self
+    }
+
+    /// 启动服务器，直到出错或 shutdown_signal 完成且所有连接关闭
+    pub async fn run(self) -> Result<(), hyper::Error> {
+        let shutdown_timeout = self.options.shutdown_timeout();
+        let (connection, shutdown) = self.options.start();
+        let connections = InFlight::new();
+        let mut app = Router::new()
+            .route(&self.path, get(|ws, headers, query, state| websocket_handler(ws, headers, query, state, Some(Codec::Protobuf))))
+            .boxed();
+        for (path, codec) in self.routes {
+            app = app
+                .route(&path, get(move |ws, headers, query, state| websocket_handler(ws, headers, query, state, codec)))
+                .boxed();
+        }
+        let app = app.layer(AddExtensionLayer::new(ServerState {
+            connection,
+            auth: self.auth,
+            connections: connections.clone(),
+        }));
+
+        axum::Server::bind(&self.addr)
+            .serve(app.into_make_service())
+            .with_graceful_shutdown(shutdown)
+            .await?;
+
+        // websocket 连接已经升级，不受 hyper 管理，需要单独等待
+        let _ = tokio::time::timeout(shutdown_timeout + shutdown::CLOSE_TIMEOUT, connections.wait()).await;
+        Ok(())
+    }
+}
+
+impl BotBuilder for BotServer {
+    fn options_mut(&mut self) -> &mut BotOptions {
+        &mut self.options
+    }
+}
+
+async fn websocket_handler(
+    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    Query(query): Query<HashMap<String, String>>,
+    Extension(state): Extension<ServerState>,
+    codec: Option<Codec>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let bot_id = state.auth.authorize(&headers, &query).map_err(|err| {
+        tracing::warn!(error = ?err, "rejected connection");
+        err.status_code()
+    })?;
+    let span = tracing::info_span!("connection", bot_id);
+    Ok(ws.on_upgrade(move |socket| async move {
+        let _connection = state.connections.enter();
+        connection::run(socket, bot_id, state.connection, codec).await
+    }.instrument(span)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::Bot;
+    use crate::context::EventContext;
+    use crate::error::BotError;
+    use crate::handler::{EventHandler, Propagation};
+    use crate::onebot::frame::Data;
+    use crate::onebot::*;
+    use crate::options::BotBuilder;
+    use crate::test_util::{binary, free_port, private_message, private_message_frame, text};
+    use async_trait::async_trait;
+    use axum::http::HeaderValue;
+    use futures::{SinkExt, StreamExt};
+    use std::time::Duration;
+    use tokio::sync::{mpsc, oneshot};
+    use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+
+    struct LoginInfo(mpsc::Sender<Result<GetLoginInfoResp, BotError>>);
+
+    #[async_trait]
+    impl EventHandler for LoginInfo {
+        async fn on_private_message(&self, ctx: &EventContext<PrivateMessageEvent>) -> Propagation {
+            let _ = self.0.send(ctx.bot.clone().get_login_info().await).await;
+            Propagation::Continue
+        }
+    }
+
+    type Client = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+    /// 以 10001 的身份连接，等待服务器启动
+    async fn connect(port: u16) -> Client {
+        connect_path(port, "/ws/cq/").await
+    }
+
+    async fn connect_path(port: u16, path: &str) -> Client {
+        handshake(port, path, &[("x-self-id", "10001")]).await.unwrap()
+    }
+
+    /// 带有 headers 的 websocket 握手，服务器还没有开始监听时重试
+    async fn handshake(port: u16, path: &str, headers: &[(&'static str, &'static str)]) -> Result<Client, tungstenite::Error> {
+        for _ in 0..100 {
+            let mut request = format!("ws://127.0.0.1:{}{}", port, path).into_client_request().unwrap();
+            for (name, value) in headers {
+                request.headers_mut().insert(*name, HeaderValue::from_static(value));
+            }
+            match tokio_tungstenite::connect_async(request).await {
+                Err(tungstenite::Error::Io(_)) => tokio::time::sleep(Duration::from_millis(10)).await,
+                result => return result.map(|(stream, _)| stream),
+            }
+        }
+        panic!("server did not start")
+    }
+
+    /// 握手被拒绝时的状态码
+    fn rejected_status(result: Result<Client, tungstenite::Error>) -> u16 {
+        match result.err() {
+            Some(tungstenite::Error::Http(resp)) => resp.status().as_u16(),
+            other => panic!("unexpected handshake result {:?}", other),
+        }
+    }
+
+    struct PrivateMessages(mpsc::Sender<i64>);
+
+    #[async_trait]
+    impl EventHandler for PrivateMessages {
+        async fn on_private_message(&self, ctx: &EventContext<PrivateMessageEvent>) -> Propagation {
+            let _ = self.0.send(ctx.user_id).await;
+            Propagation::Continue
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_on_configured_path_reach_handler() {
+        let port = free_port();
+        let (received_sender, mut received) = mpsc::channel(10);
+        let (_signal_sender, signal_receiver) = oneshot::channel::<()>();
+        tokio::spawn(BotServer::new()
+            .bind(([127, 0, 0, 1], port))
+            .path("/bot/")
+            .handler(PrivateMessages(received_sender))
+            .shutdown_signal(async {
+                let _ = signal_receiver.await;
+            })
+            .run());
+
+        let mut client = connect_path(port, "/bot/").await;
+        client.send(private_message()).await.unwrap();
+        assert_eq!(received.recv().await, Some(1));
+
+        // 修改路径后默认路径不再可用
+        assert_eq!(rejected_status(handshake(port, "/ws/cq/", &[("x-self-id", "10001")]).await), 404);
+    }
+
+    #[tokio::test]
+    async fn upgrade_requires_token_and_allowed_bot() {
+        let port = free_port();
+        let (_signal_sender, signal_receiver) = oneshot::channel::<()>();
+        tokio::spawn(BotServer::new()
+            .bind(([127, 0, 0, 1], port))
+            .auth(Auth::new().access_token("secret").allow_bot(10001))
+            .shutdown_signal(async {
+                let _ = signal_receiver.await;
+            })
+            .run());
+
+        // Authorization 头或 access_token 参数
+        assert!(handshake(port, "/ws/cq/", &[("x-self-id", "10001"), ("authorization", "Bearer secret")]).await.is_ok());
+        assert!(handshake(port, "/ws/cq/?access_token=secret", &[("x-self-id", "10001")]).await.is_ok());
+
+        // 没有 token 或 token 错误
+        assert_eq!(rejected_status(handshake(port, "/ws/cq/", &[("x-self-id", "10001")]).await), 401);
+        assert_eq!(rejected_status(handshake(port, "/ws/cq/", &[("x-self-id", "10001"), ("authorization", "Bearer wrong")]).await), 401);
+        assert_eq!(rejected_status(handshake(port, "/ws/cq/?access_token=wrong", &[("x-self-id", "10001")]).await), 401);
+
+        // 不在允许列表中的机器人
+        assert_eq!(rejected_status(handshake(port, "/ws/cq/", &[("x-self-id", "20002"), ("authorization", "Bearer secret")]).await), 403);
+    }
+
+    #[tokio::test]
+    async fn shutdown_drains_handlers_then_closes() {
+        let port = free_port();
+        let (results_sender, mut results) = mpsc::channel(10);
+        let (signal_sender, signal_receiver) = oneshot::channel::<()>();
+        let server = tokio::spawn(BotServer::new()
+            .bind(([127, 0, 0, 1], port))
+            .handler(LoginInfo(results_sender))
+            .shutdown_signal(async {
+                let _ = signal_receiver.await;
+            })
+            .run());
+
+        let mut client = connect(port).await;
+
+        // 处理器发出 API 请求后开始退出
+        client.send(private_message()).await.unwrap();
+        let req = match client.next().await.unwrap().unwrap() {
+            tungstenite::Message::Binary(buf) => <Frame as prost::Message>::decode(buf.as_ref()).unwrap(),
+            other => panic!("unexpected message {:?}", other),
+        };
+        assert!(matches!(req.data, Some(Data::GetLoginInfoReq(_))));
+        signal_sender.send(()).unwrap();
+
+        // 退出期间的新事件不再处理，正在进行的 API 调用仍然可以收到响应
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        client.send(private_message()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        client.send(binary(Frame {
+            echo: req.echo,
+            ok: true,
+            data: Some(Data::GetLoginInfoResp(GetLoginInfoResp { user_id: 10001, nickname: "bot".to_string() })),
+            ..Default::default()
+        })).await.unwrap();
+        assert_eq!(results.recv().await.unwrap().unwrap().nickname, "bot");
+
+        match client.next().await.unwrap().unwrap() {
+            tungstenite::Message::Close(Some(frame)) => assert_eq!(u16::from(frame.code), 1001),
+            other => panic!("unexpected message {:?}", other),
+        }
+        // 回复 Close
+        while let Some(Ok(_)) = client.next().await {}
+
+        tokio::time::timeout(Duration::from_secs(5), server).await.unwrap().unwrap().unwrap();
+        assert!(results.recv().await.is_none());
+    }
+
+    struct DecodeErrors(mpsc::Sender<usize>);
+
+    #[async_trait]
+    impl EventHandler for DecodeErrors {
+        async fn on_decode_error(&self, _bot: Bot, error: &BotError, raw: &[u8]) {
+            assert!(matches!(error, BotError::Decode(_)));
+            let _ = self.0.send(raw.len()).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn decode_errors_keep_connection_until_threshold() {
+        let port = free_port();
+        let (errors_sender, mut errors) = mpsc::channel(10);
+        let (results_sender, mut results) = mpsc::channel(10);
+        let (_signal_sender, signal_receiver) = oneshot::channel::<()>();
+        tokio::spawn(BotServer::new()
+            .bind(([127, 0, 0, 1], port))
+            .handler(DecodeErrors(errors_sender))
+            .handler(LoginInfo(results_sender))
+            .max_decode_errors(3)
+            .shutdown_signal(async {
+                let _ = signal_receiver.await;
+            })
+            .run());
+        let mut client = connect(port).await;
+        let garbage = || tungstenite::Message::Binary(vec![0xff; 4]);
+
+        // 解码失败后连接仍然可用，成功解码后重新计数
+        client.send(garbage()).await.unwrap();
+        client.send(garbage()).await.unwrap();
+        client.send(private_message()).await.unwrap();
+        assert!(matches!(client.next().await.unwrap().unwrap(), tungstenite::Message::Binary(_)));
+        assert_eq!(errors.recv().await, Some(4));
+        assert_eq!(errors.recv().await, Some(4));
+
+        // 连续 3 次失败后断开
+        for _ in 0..3 {
+            client.send(garbage()).await.unwrap();
+        }
+        match client.next().await.unwrap().unwrap() {
+            tungstenite::Message::Close(Some(frame)) => assert_eq!(u16::from(frame.code), 1007),
+            other => panic!("unexpected message {:?}", other),
+        }
+        // 正在等待的 API 调用因为连接断开而失败
+        assert!(matches!(results.recv().await, Some(Err(BotError::ChannelClosed))));
+    }
+
+    /// 收到 Binary 格式的 get_login_info 请求，回复 nickname
+    async fn reply_login_info(client: &mut Client, nickname: &str) {
+        let req = match client.next().await.unwrap().unwrap() {
+            tungstenite::Message::Binary(buf) => <Frame as prost::Message>::decode(buf.as_ref()).unwrap(),
+            other => panic!("unexpected message {:?}", other),
+        };
+        assert!(matches!(req.data, Some(Data::GetLoginInfoReq(_))));
+        client.send(binary(Frame {
+            echo: req.echo,
+            ok: true,
+            data: Some(Data::GetLoginInfoResp(GetLoginInfoResp { user_id: 10001, nickname: nickname.to_string() })),
+            ..Default::default()
+        })).await.unwrap();
+    }
+
+    /// 收到 Text 格式的 get_login_info 请求，回复 nickname
+    async fn reply_login_info_json(client: &mut Client, nickname: &str) {
+        let req: Frame = match client.next().await.unwrap().unwrap() {
+            tungstenite::Message::Text(json) => serde_json::from_str(&json).unwrap(),
+            other => panic!("unexpected message {:?}", other),
+        };
+        assert!(matches!(req.data, Some(Data::GetLoginInfoReq(_))));
+        client.send(text(Frame {
+            echo: req.echo,
+            ok: true,
+            data: Some(Data::GetLoginInfoResp(GetLoginInfoResp { user_id: 10001, nickname: nickname.to_string() })),
+            ..Default::default()
+        })).await.unwrap();
+    }
+
+    struct ConnectedLoginInfo(mpsc::Sender<Result<GetLoginInfoResp, BotError>>);
+
+    #[async_trait]
+    impl EventHandler for ConnectedLoginInfo {
+        async fn on_bot_connected(&self, mut bot: Bot) {
+            let _ = self.0.send(bot.get_login_info().await).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn default_path_sends_protobuf_immediately() {
+        let port = free_port();
+        let (results_sender, mut results) = mpsc::channel(10);
+        let (_signal_sender, signal_receiver) = oneshot::channel::<()>();
+        tokio::spawn(BotServer::new()
+            .bind(([127, 0, 0, 1], port))
+            .handler(ConnectedLoginInfo(results_sender))
+            .shutdown_signal(async {
+                let _ = signal_receiver.await;
+            })
+            .run());
+
+        // 对端没有发送任何 Frame，连接后的请求也直接使用 Protobuf 发送
+        let mut client = connect(port).await;
+        reply_login_info(&mut client, "connected").await;
+        assert_eq!(results.recv().await.unwrap().unwrap().nickname, "connected");
+    }
+
+    #[tokio::test]
+    async fn requests_wait_for_detected_codec() {
+        let port = free_port();
+        let (results_sender, mut results) = mpsc::channel(10);
+        let (_signal_sender, signal_receiver) = oneshot::channel::<()>();
+        tokio::spawn(BotServer::new()
+            .bind(([127, 0, 0, 1], port))
+            .detect_route("/ws/auto/")
+            .handler(ConnectedLoginInfo(results_sender))
+            .shutdown_signal(async {
+                let _ = signal_receiver.await;
+            })
+            .run());
+
+        // 连接后立即发出的请求在收到第一个 Frame 之前不会发送
+        let mut client = connect_path(port, "/ws/auto/").await;
+        assert!(tokio::time::timeout(Duration::from_millis(50), client.next()).await.is_err());
+        client.send(text(private_message_frame())).await.unwrap();
+        reply_login_info_json(&mut client, "connected").await;
+        assert_eq!(results.recv().await.unwrap().unwrap().nickname, "connected");
+    }
+
+    #[tokio::test]
+    async fn json_connections_use_text_frames() {
+        let port = free_port();
+        let (results_sender, mut results) = mpsc::channel(10);
+        let (_signal_sender, signal_receiver) = oneshot::channel::<()>();
+        tokio::spawn(BotServer::new()
+            .bind(([127, 0, 0, 1], port))
+            .route("/ws/json/", Codec::Json)
+            .detect_route("/ws/auto/")
+            .handler(LoginInfo(results_sender))
+            .shutdown_signal(async {
+                let _ = signal_receiver.await;
+            })
+            .run());
+
+        // 由第一个 Frame 确定为 JSON，之后的 Binary 消息被忽略
+        let mut client = connect_path(port, "/ws/auto/").await;
+        client.send(text(private_message_frame())).await.unwrap();
+        client.send(private_message()).await.unwrap();
+        reply_login_info_json(&mut client, "auto").await;
+        assert_eq!(results.recv().await.unwrap().unwrap().nickname, "auto");
+
+        // 固定 JSON 的路径
+        let mut client = connect_path(port, "/ws/json/").await;
+        client.send(private_message()).await.unwrap();
+        client.send(text(private_message_frame())).await.unwrap();
+        reply_login_info_json(&mut client, "fixed").await;
+        assert_eq!(results.recv().await.unwrap().unwrap().nickname, "fixed");
+        assert!(tokio::time::timeout(Duration::from_millis(50), results.recv()).await.is_err());
+    }
+}
@@ -0,0 +1,291 @@
+use crate::error::BotError;
+use crate::onebot::frame::Data;
+use crate::onebot::*;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tokio::sync::oneshot;
+
+/// 私聊或群消息事件
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageEvent {
+    Private(PrivateMessageEvent),
+    Group(GroupMessageEvent),
+}
+
+impl MessageEvent {
+    pub fn from_data(data: &Data) -> Option<MessageEvent> {
+        match data {
+            Data::PrivateMessageEvent(event) => Some(MessageEvent::Private(event.clone())),
+            Data::GroupMessageEvent(event) => Some(MessageEvent::Group(event.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn user_id(&self) -> i64 {
+        match self {
+            MessageEvent::Private(event) => event.user_id,
+            MessageEvent::Group(event) => event.user_id,
+        }
+    }
+
+    /// 私聊时为 None
+    pub fn group_id(&self) -> Option<i64> {
+        match self {
+            MessageEvent::Private(_) => None,
+            MessageEvent::Group(event) => Some(event.group_id),
+        }
+    }
+
+    pub fn message_id(&self) -> i32 {
+        match self {
+            MessageEvent::Private(event) => event.message_id,
+            MessageEvent::Group(event) => event.message_id,
+        }
+    }
+
+    pub fn message(&self) -> &[Message] {
+        match self {
+            MessageEvent::Private(event) => &event.message,
+            MessageEvent::Group(event) => &event.message,
+        }
+    }
+}
+
+/// 在锁外执行，需要和 Waiter 共享
+type Filter = Arc<dyn Fn(&MessageEvent) -> bool + Send + Sync>;
+
+struct Waiter {
+    id: u64,
+    user_id: i64,
+    group_id: Option<i64>,
+    filter: Filter,
+    sender: oneshot::Sender<Result<MessageEvent, BotError>>,
+}
+
+#[derive(Default)]
+struct SessionsInner {
+    next_id: u64,
+    waiters: Vec<Waiter>,
+}
+
+///
+/// 等待用户下一条消息的会话，每个 Bot 连接一份
+///
+/// 消息事件分发前先交给等待中的会话，被会话接收的消息不再交给 EventHandler
+///
+#[derive(Clone, Default)]
+pub struct Sessions {
+    inner: Arc<Mutex<SessionsInner>>,
+}
+
+impl Sessions {
+    /// 会话列表，锁中途 panic 不影响之后的会话
+    fn lock(&self) -> MutexGuard<'_, SessionsInner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn register(&self, user_id: i64, group_id: Option<i64>, filter: Filter) -> (u64, oneshot::Receiver<Result<MessageEvent, BotError>>) {
+        let (sender, receiver) = oneshot::channel();
+        let mut inner = self.lock();
+        inner.next_id += 1;
+        let id = inner.next_id;
+        inner.waiters.push(Waiter { id, user_id, group_id, filter, sender });
+        (id, receiver)
+    }
+
+    fn remove(&self, id: u64) {
+        self.lock().waiters.retain(|waiter| waiter.id != id);
+    }
+
+    ///
+    /// 等待 user_id 的下一条消息
+    ///
+    /// @param user_id  QQ 号
+    /// @param group_id 群号，None 表示等待私聊消息
+    /// @param timeout  超时时间
+    /// @param filter   只接收满足条件的消息，其他消息照常分发
+    /// @return 超时返回 BotError::Timeout，被取消返回 BotError::Cancelled，连接断开返回 BotError::ChannelClosed
+    ///
+    pub async fn wait_next<F>(&self, user_id: i64, group_id: Option<i64>, timeout: std::time::Duration, filter: F) -> Result<MessageEvent, BotError>
+    where
+        F: Fn(&MessageEvent) -> bool + Send + Sync + 'static,
+    {
+        let (id, receiver) = self.register(user_id, group_id, Arc::new(filter));
+        // future 被 drop 时同样移除
+        let _guard = WaiterGuard { sessions: self, id };
+        match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(BotError::ChannelClosed),
+            Err(_) => Err(BotError::Timeout),
+        }
+    }
+
+    ///
+    /// 把消息交给等待中的会话，先等待的先接收
+    ///
+    /// filter 在锁外执行，panic 的 filter 视为不匹配
+    ///
+    /// @param data 收到的事件
+    /// @return 是否被会话接收
+    ///
+    pub fn offer(&self, data: &Data) -> bool {
+        let event = match MessageEvent::from_data(data) {
+            Some(event) => event,
+            None => return false,
+        };
+        let candidates: Vec<(u64, Filter)> = self.lock().waiters.iter()
+            .filter(|waiter| waiter.user_id == event.user_id() && waiter.group_id == event.group_id())
+            .map(|waiter| (waiter.id, waiter.filter.clone()))
+            .collect();
+        for (id, filter) in candidates {
+            match panic::catch_unwind(AssertUnwindSafe(|| filter(&event))) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(_) => {
+                    tracing::error!(user_id = event.user_id(), "session filter panicked");
+                    continue;
+                }
+            }
+            // 执行 filter 期间会话可能已经结束
+            let mut inner = self.lock();
+            let waiter = match inner.waiters.iter().position(|waiter| waiter.id == id) {
+                Some(index) => inner.waiters.remove(index),
+                None => continue,
+            };
+            drop(inner);
+            if waiter.sender.send(Ok(event.clone())).is_ok() {
+                return true;
+            }
+        }
+        false
+    }
+
+    ///
+    /// 取消等待 user_id 消息的会话
+    ///
+    /// @return 取消的会话数量
+    ///
+    pub fn cancel(&self, user_id: i64, group_id: Option<i64>) -> usize {
+        let mut inner = self.lock();
+        let (cancelled, waiters): (Vec<Waiter>, Vec<Waiter>) = inner.waiters.drain(..)
+            .partition(|waiter| waiter.user_id == user_id && waiter.group_id == group_id);
+        inner.waiters = waiters;
+        let count = cancelled.len();
+        for waiter in cancelled {
+            let _ = waiter.sender.send(Err(BotError::Cancelled));
+        }
+        count
+    }
+
+    /// 连接断开时调用，所有会话返回 BotError::ChannelClosed
+    pub fn clear(&self) {
+        self.lock().waiters.clear();
+    }
+}
+
+struct WaiterGuard<'a> {
+    sessions: &'a Sessions,
+    id: u64,
+}
+
+impl Drop for WaiterGuard<'_> {
+    fn drop(&mut self) {
+        self.sessions.remove(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::Bot;
+    use crate::context::EventContext;
+    use crate::dispatcher::Dispatcher;
+    use crate::handler::{EventHandler, Propagation};
+    use crate::msg::text;
+    use async_trait::async_trait;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<i64>>>);
+
+    #[async_trait]
+    impl EventHandler for Recorder {
+        async fn on_group_message(&self, ctx: &EventContext<GroupMessageEvent>) -> Propagation {
+            self.0.lock().unwrap().push(ctx.user_id);
+            Propagation::Continue
+        }
+    }
+
+    fn group_message(group_id: i64, user_id: i64, content: &str) -> Data {
+        Data::GroupMessageEvent(GroupMessageEvent {
+            group_id,
+            user_id,
+            message: vec![text(content)],
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn wait_next_consumes_matching_message() {
+        let (api_sender, _api_receiver) = mpsc::channel(10);
+        let bot = Bot::new(10001, api_sender);
+        let recorder = Recorder::default();
+        let dispatcher = Dispatcher::new().add_handler(recorder.clone());
+
+        let waiting = tokio::spawn({
+            let bot = bot.clone();
+            async move {
+                bot.wait_next_filtered(1, Some(100), Duration::from_secs(1), |event| event.message().len() == 1).await
+            }
+        });
+        let _ = tokio::task::yield_now().await;
+
+        dispatcher.dispatch(bot.clone(), &group_message(100, 2, "other user")).await;
+        dispatcher.dispatch(bot.clone(), &group_message(200, 1, "other group")).await;
+        dispatcher.dispatch(bot.clone(), &group_message(100, 1, "answer")).await;
+        dispatcher.dispatch(bot.clone(), &group_message(100, 1, "after")).await;
+
+        let event = waiting.await.unwrap().unwrap();
+        assert_eq!(event.message(), &[text("answer")][..]);
+        assert_eq!(*recorder.0.lock().unwrap(), vec![2, 1, 1]);
+    }
+
+    #[tokio::test]
+    async fn wait_next_timeout_and_cancel() {
+        let sessions = Sessions::default();
+        let err = sessions.wait_next(1, None, Duration::from_millis(10), |_| true).await.unwrap_err();
+        assert!(matches!(err, BotError::Timeout));
+        assert!(sessions.inner.lock().unwrap().waiters.is_empty());
+
+        let waiting = tokio::spawn({
+            let sessions = sessions.clone();
+            async move { sessions.wait_next(1, None, Duration::from_secs(1), |_| true).await }
+        });
+        let _ = tokio::task::yield_now().await;
+        assert_eq!(sessions.cancel(1, None), 1);
+        assert!(matches!(waiting.await.unwrap(), Err(BotError::Cancelled)));
+    }
+
+    #[tokio::test]
+    async fn panicking_filter_does_not_break_sessions() {
+        let sessions = Sessions::default();
+        let panicking = tokio::spawn({
+            let sessions = sessions.clone();
+            async move { sessions.wait_next(1, Some(100), Duration::from_millis(100), |_| panic!("bad filter")).await }
+        });
+        let waiting = tokio::spawn({
+            let sessions = sessions.clone();
+            async move { sessions.wait_next(1, Some(100), Duration::from_secs(1), |_| true).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // panic 的 filter 视为不匹配，消息交给下一个会话
+        assert!(sessions.offer(&group_message(100, 1, "answer")));
+        assert_eq!(waiting.await.unwrap().unwrap().message(), &[text("answer")][..]);
+        assert!(!sessions.offer(&group_message(100, 1, "again")));
+        assert!(!sessions.inner.is_poisoned());
+        assert!(matches!(panicking.await.unwrap(), Err(BotError::Timeout)));
+        assert_eq!(sessions.cancel(1, Some(100)), 0);
+    }
+}
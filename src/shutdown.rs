@@ -0,0 +1,138 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+use tokio::time::Instant;
+
+/// 默认等待事件处理器和 API 调用完成的时间
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 发送 Close 帧后等待对端回复的时间
+pub const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// 等待 SIGINT（Ctrl-C）或 SIGTERM
+pub async fn signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = ctrl_c => {}
+                _ = terminate.recv() => {}
+            },
+            Err(_) => ctrl_c.await,
+        }
+    }
+    #[cfg(not(unix))]
+    ctrl_c.await;
+}
+
+/// 完成后开始退出
+pub(crate) type ShutdownSignal = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// 开始退出时发送截止时间，连接在截止时间前完成正在进行的工作
+pub(crate) type ShutdownReceiver = watch::Receiver<Option<Instant>>;
+
+/// 等待开始退出，返回截止时间
+pub(crate) async fn deadline(shutdown: &mut ShutdownReceiver) -> Instant {
+    loop {
+        if let Some(deadline) = *shutdown.borrow() {
+            return deadline;
+        }
+        if shutdown.changed().await.is_err() {
+            // 服务器没有退出就结束了，不会再收到退出通知
+            futures::future::pending::<()>().await;
+        }
+    }
+}
+
+///
+/// 正在进行的任务计数，用于退出时等待任务完成
+///
+/// 等待期间开始的任务同样会被计数和等待
+///
+#[derive(Clone, Default)]
+pub(crate) struct InFlight(Arc<Counter>);
+
+#[derive(Default)]
+struct Counter {
+    count: AtomicUsize,
+    /// 计数归零时通知
+    idle: Notify,
+}
+
+/// 持有期间任务计为进行中
+pub(crate) struct InFlightGuard(Arc<Counter>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl InFlight {
+    pub(crate) fn new() -> InFlight {
+        Default::default()
+    }
+
+    /// 任务开始时调用，持有返回值直到任务结束
+    pub(crate) fn enter(&self) -> InFlightGuard {
+        self.0.count.fetch_add(1, Ordering::AcqRel);
+        InFlightGuard(self.0.clone())
+    }
+
+    /// 等待所有任务结束
+    pub(crate) async fn wait(&self) {
+        loop {
+            // 先注册通知再检查计数，避免错过检查之后的归零
+            let idle = self.0.idle.notified();
+            if self.0.count.load(Ordering::Acquire) == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn in_flight_waits_for_all_guards() {
+        let in_flight = InFlight::new();
+        let guards = vec![in_flight.enter(), in_flight.enter()];
+        let mut waiting = tokio::spawn({
+            let in_flight = in_flight.clone();
+            async move { in_flight.wait().await }
+        });
+        assert!(tokio::time::timeout(Duration::from_millis(10), &mut waiting).await.is_err());
+        drop(guards);
+        tokio::time::timeout(Duration::from_secs(1), waiting).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn in_flight_tracks_tasks_entered_while_waiting() {
+        let in_flight = InFlight::new();
+        let first = in_flight.enter();
+        let mut waiting = tokio::spawn({
+            let in_flight = in_flight.clone();
+            async move { in_flight.wait().await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let second = in_flight.enter();
+        drop(first);
+        assert!(tokio::time::timeout(Duration::from_millis(10), &mut waiting).await.is_err());
+        drop(second);
+        tokio::time::timeout(Duration::from_secs(1), waiting).await.unwrap().unwrap();
+        // 没有任务时立即返回
+        in_flight.wait().await;
+    }
+}
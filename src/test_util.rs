@@ -0,0 +1,34 @@
+//! BotServer 和 BotClient 测试共用的 websocket 消息和端口
+
+use crate::onebot::frame::Data;
+use crate::onebot::{Frame, PrivateMessageEvent};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Protobuf 编码的 Binary 消息
+pub(crate) fn binary(frame: Frame) -> Message {
+    let mut buf = Vec::new();
+    prost::Message::encode(&frame, &mut buf).unwrap();
+    Message::Binary(buf)
+}
+
+/// JSON 编码的 Text 消息
+pub(crate) fn text(frame: Frame) -> Message {
+    Message::Text(serde_json::to_string(&frame).unwrap())
+}
+
+/// user_id 为 1 的私聊消息事件
+pub(crate) fn private_message_frame() -> Frame {
+    Frame {
+        data: Some(Data::PrivateMessageEvent(PrivateMessageEvent { user_id: 1, ..Default::default() })),
+        ..Default::default()
+    }
+}
+
+pub(crate) fn private_message() -> Message {
+    binary(private_message_frame())
+}
+
+/// 当前空闲的端口
+pub(crate) fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}